@@ -0,0 +1,98 @@
+/*
+ * C interface to rustfuck. Build the shared library with `cargo build --release`, which produces
+ * target/release/librustfuck.so (or .dylib / .dll), and link against it.
+ *
+ * This header mirrors src/ffi.rs, and has to be kept in step with it. The tests in src/ffi.rs check
+ * that the RF_* constants, the function prototypes, the callback types and the fields of
+ * rf_run_options match the library.
+ */
+
+#ifndef RUSTFUCK_H
+#define RUSTFUCK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Error codes, the same as the rustfuck binary's exit codes. 0 means success. */
+#define RF_ERR_IO            1
+#define RF_ERR_CONFIG        2
+#define RF_ERR_SYNTAX        3
+#define RF_ERR_RUNTIME       4
+#define RF_ERR_INVALID_DATA  5
+#define RF_ERR_OUTPUT_LIMIT  6
+#define RF_ERR_LINT          7
+/* rustfuck panicked. This is a bug, and rf_last_error has the panic's message. */
+#define RF_ERR_PANIC         101
+
+/* Optimizer passes for rf_optimize. */
+#define RF_OPT_COLLAPSE_DUPLICATED  1u
+#define RF_OPT_ZERO_CELL            2u
+#define RF_OPT_MOVE_VALUE           4u
+#define RF_OPT_ALL                  7u
+
+/* Token types returned by rf_program_token. */
+#define RF_TOKEN_END         1
+#define RF_TOKEN_ADD         2
+#define RF_TOKEN_SUB         3
+#define RF_TOKEN_LEFT        4
+#define RF_TOKEN_RIGHT       5
+#define RF_TOKEN_OUT         6
+#define RF_TOKEN_IN          7
+#define RF_TOKEN_LOOP_START  8
+#define RF_TOKEN_LOOP_END    9
+#define RF_TOKEN_SET         10
+#define RF_TOKEN_MOVE        11
+#define RF_TOKEN_DEBUG       12
+
+/* What , stores at the end of input. */
+#define RF_EOF_ZERO       0
+#define RF_EOF_MINUS_ONE  1
+#define RF_EOF_UNCHANGED  2
+
+typedef struct rf_program rf_program;
+
+/* Reads a value for , into *value. Returns 1 if a value was read, 0 at the end of the input, or
+ * -1 on error. */
+typedef int (*rf_read_fn)(void *ctx, uint64_t *value);
+
+/* Writes a value for . and returns 0, or anything else on error. */
+typedef int (*rf_write_fn)(void *ctx, uint64_t value);
+
+typedef struct rf_run_options {
+    size_t tape_size;     /* number of cells on the tape, e.g. 30000 */
+    int eof;              /* one of the RF_EOF_* values */
+    unsigned cell_bits;   /* 8, 16, 32 or 64, or 0 for automatic width detection */
+} rf_run_options;
+
+/* The message for the last error on this thread, or an empty string. Valid until the next failing
+ * call on the same thread. */
+const char *rf_last_error(void);
+
+/* Parses len bytes of source code and stores the program in *out. */
+int rf_parse(const char *source, size_t len, rf_program **out);
+
+/* Runs the optimizer passes chosen by a combination of the RF_OPT_* flags. */
+void rf_optimize(rf_program *program, unsigned passes);
+
+/* The number of tokens in the program, including the final RF_TOKEN_END. */
+size_t rf_program_len(const rf_program *program);
+
+/* Stores the type and value of the token at index. Returns 0, or -1 if index is out of range. */
+int rf_program_token(const rf_program *program, size_t index, int *type, int32_t *value);
+
+/* Runs the program, calling read for , and write for . with ctx as their first argument. */
+int rf_run(const rf_program *program, const rf_run_options *options,
+           rf_read_fn read, rf_write_fn write, void *ctx);
+
+/* Frees a program returned by rf_parse. Does nothing if program is NULL. */
+void rf_program_free(rf_program *program);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUSTFUCK_H */
@@ -0,0 +1,705 @@
+use std::{fmt, str};
+
+use crate::json;
+
+/// The kind of an instruction in the intermediate representation.
+///
+/// The meaning of a token's `value` depends on its type, as described for each variant.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenType {
+    /// Never produced by the parser; used by optimizer passes as a placeholder.
+    Invalid,
+    /// The end of the program.
+    End,
+    /// Add `value` to the current cell.
+    Add,
+    /// Subtract `value` from the current cell.
+    Sub,
+    /// Move the data pointer `value` cells left.
+    Left,
+    /// Move the data pointer `value` cells right.
+    Right,
+    /// Write the current cell `value` times.
+    Out,
+    /// Read a value into the current cell.
+    In,
+    /// Jump to the instruction after the matching `LoopEnd`, at index `value`, if the current cell is zero.
+    LoopStart,
+    /// Jump back to the matching `LoopStart`, at index `value`, if the current cell is nonzero.
+    LoopEnd,
+    /// Set the current cell to `value`.
+    Set,
+    /// Add the current cell to the cell `value` cells away, if the current cell is nonzero.
+    Move,
+    /// Print the data pointer and the cells around it to stderr. Only produced when a debug
+    /// character is chosen with `ParseConfig::debug_char`.
+    Debug,
+}
+
+/// The part of the source code a token came from.
+///
+/// Tokens which weren't parsed from source code, such as those loaded from a `.bfc` file, have the
+/// default span, with a line of 0.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    /// The byte offset of the first character.
+    pub start: usize,
+    /// The byte offset just past the last character.
+    pub end: usize,
+    /// The line of the first character, starting at 1.
+    pub line: usize,
+    /// The column of the first character, starting at 1.
+    pub col: usize,
+}
+
+impl Span {
+    /// The smallest span covering both spans.
+    pub fn merge(&self, other: &Span) -> Span {
+        let first = if other.start < self.start { other } else { self };
+        Span {
+            start: first.start,
+            end: self.end.max(other.end),
+            line: first.line,
+            col: first.col,
+        }
+    }
+}
+
+/// A single instruction in the intermediate representation.
+#[derive(Debug)]
+pub struct Token {
+    pub tk: TokenType,
+    pub value: i32,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(tk: TokenType, value: i32) -> Token {
+        Token {
+            tk,
+            value,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(tk: TokenType, value: i32, span: Span) -> Token {
+        Token {
+            tk,
+            value,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    /// Shows the token as it appears in dumps, e.g. `+5` or `S0`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&token_text(self))
+    }
+}
+
+/// A program in the intermediate representation.
+pub type Ast = Vec<Token>;
+
+/// How many characters of source code are shown for each token in an annotated dump, and for each
+/// line in a side-by-side dump, before it's cut short.
+const SOURCE_WIDTH: usize = 48;
+
+/// How many tokens are put on each line of a node in a Graphviz dump.
+const DOT_TOKENS_PER_LINE: usize = 12;
+
+/// The text a token is shown as in dumps, e.g. `+5` or `S0`.
+fn token_text(token: &Token) -> String {
+    match token.tk {
+        TokenType::Add => {
+            if token.value == 1 {
+                "+".to_string()
+            } else {
+                format!("+{}", token.value)
+            }
+        },
+        TokenType::Sub => {
+            if token.value == 1 {
+                "-".to_string()
+            } else {
+                format!("-{}", token.value)
+            }
+        },
+        TokenType::Left => {
+            if token.value == 1 {
+                "<".to_string()
+            } else {
+                format!("<{}", token.value)
+            }
+        },
+        TokenType::Right => {
+            if token.value == 1 {
+                ">".to_string()
+            } else {
+                format!(">{}", token.value)
+            }
+        },
+        TokenType::In => {
+            ",".to_string()
+        },
+        TokenType::Out => {
+            if token.value == 1 {
+                ".".to_string()
+            } else {
+                format!(".{}", token.value)
+            }
+        },
+        TokenType::LoopStart => {
+            "[".to_string()
+        },
+        TokenType::LoopEnd => {
+            "]".to_string()
+        },
+        TokenType::Set => {
+            format!("S{}", token.value)
+        },
+        TokenType::Move => {
+            format!("M{}", token.value)
+        },
+        TokenType::Invalid => {
+            "INVALID".to_string()
+        },
+        TokenType::Debug => {
+            "#".to_string()
+        },
+        TokenType::End => {
+            ":".to_string()
+        },
+    }
+}
+
+/// Shortens text to at most `width` characters, on one line, for a dump column.
+fn clip(text: &str, width: usize) -> String {
+    let text: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= width {
+        text
+    } else {
+        let mut clipped: String = text.chars().take(width - 3).collect();
+        clipped.push_str("...");
+        clipped
+    }
+}
+
+/// How `--dump` lays out the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DumpView {
+    /// The tokens alone, indented by loop depth.
+    Tokens,
+    /// One token per line, with the source code it came from.
+    Annotated,
+    /// Each line of source code next to the tokens made from it.
+    SideBySide,
+}
+
+impl str::FromStr for DumpView {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<DumpView, &'static str> {
+        match s {
+            "tokens" => Ok(DumpView::Tokens),
+            "annotated" => Ok(DumpView::Annotated),
+            "side-by-side" => Ok(DumpView::SideBySide),
+            _ => Err("Unknown dump view"),
+        }
+    }
+}
+
+/// The format `--dump` writes the program in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AstFormat {
+    /// Plain text, laid out according to a `DumpView`.
+    Text,
+    /// A list of tokens with their link targets and source positions.
+    Json,
+    /// A control-flow graph for Graphviz, with a node for each straight run of tokens and each
+    /// loop bracket.
+    Dot,
+    /// A web page of the source code, with each piece marked with the tokens made from it.
+    Html,
+}
+
+impl str::FromStr for AstFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<AstFormat, &'static str> {
+        match s {
+            "text" => Ok(AstFormat::Text),
+            "json" => Ok(AstFormat::Json),
+            "dot" => Ok(AstFormat::Dot),
+            "html" => Ok(AstFormat::Html),
+            _ => Err("Unknown dump format"),
+        }
+    }
+}
+
+/// Formats an AST as human-readable text.
+pub trait Dumpable {
+    fn dump(&self) -> String;
+}
+
+impl Dumpable for Ast {
+    fn dump(&self) -> String {
+        let mut out = String::new();
+        let mut depth = 0;
+        let mut line = String::new();
+        for token in self.iter() {
+            let end_line = token.tk == TokenType::LoopStart || token.tk == TokenType::LoopEnd;
+
+            let mut part = token_text(token);
+            part.push(' ');
+
+            if ! end_line {
+                line.push_str(&part);
+            }
+
+            if line.len() >= 80 || end_line {
+                out.push_str(
+                    &format!("{}{}\n",
+                        "  ".repeat(depth),
+                        line
+                    )
+                );
+                line.clear();
+            }
+
+            if token.tk == TokenType::LoopEnd {
+                depth -= 1;
+            }
+
+            if end_line {
+                out.push_str(
+                    &format!("{}{}\n",
+                        "  ".repeat(depth),
+                        part
+                    )
+                );
+            }
+
+            if token.tk == TokenType::LoopStart {
+                depth += 1;
+            }
+        }
+
+        if !line.is_empty() {
+            out.push_str(
+                &format!("{}{}\n",
+                    "  ".repeat(depth),
+                    line
+                )
+            );
+        }
+
+        out
+    }
+}
+
+/// Lists the tokens one per line, each with the position and text of the source code it was
+/// made from. `source` must be the code the AST was parsed from. A `LoopEnd` without a matching
+/// `LoopStart`, which only hand-built ASTs have, is shown at the outermost level.
+pub fn dump_annotated(ast: &[Token], source: &str) -> String {
+    let width = ast.iter()
+        .scan(0usize, |depth, token| {
+            if token.tk == TokenType::LoopEnd {
+                *depth = depth.saturating_sub(1);
+            }
+            let width = *depth * 2 + token_text(token).len();
+            if token.tk == TokenType::LoopStart {
+                *depth += 1;
+            }
+            Some(width)
+        })
+        .chain(Some("TOKEN".len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut out = format!("{:>5}  {:width$}  {:9}  SOURCE\n", "INDEX", "TOKEN", "LINE:COL", width = width);
+    let mut depth: usize = 0;
+    for (i, token) in ast.iter().enumerate() {
+        if token.tk == TokenType::LoopEnd {
+            depth = depth.saturating_sub(1);
+        }
+
+        let text = format!("{}{}", "  ".repeat(depth), token_text(token));
+        let (position, original) = match source.get(token.span.start..token.span.end) {
+            Some(x) if token.span.line > 0 => (format!("{}:{}", token.span.line, token.span.col), clip(x, SOURCE_WIDTH)),
+            _ => (String::from("-"), String::new()),
+        };
+        out.push_str(format!("{:>5}  {:width$}  {:9}  {}", i, text, position, original, width = width).trim_end());
+        out.push('\n');
+
+        if token.tk == TokenType::LoopStart {
+            depth += 1;
+        }
+    }
+
+    out
+}
+
+/// Shows each line of `source` next to the tokens which were made from it. `source` must be
+/// the code the AST was parsed from.
+pub fn dump_side_by_side(ast: &[Token], source: &str) -> String {
+    let lines: Vec<String> = source.lines()
+        .map(|line| clip(&line.replace('\t', "    "), SOURCE_WIDTH))
+        .collect();
+    let width = lines.iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let gutter = lines.len().max(1).to_string().len();
+
+    let mut tokens: Vec<Vec<String>> = vec![Vec::new(); lines.len()];
+    let mut unplaced = Vec::new();
+    for token in ast.iter().filter(|t| t.tk != TokenType::End) {
+        match tokens.get_mut(token.span.line.wrapping_sub(1)) {
+            Some(x) => x.push(token_text(token)),
+            None => unplaced.push(token_text(token)),
+        }
+    }
+
+    let mut out = String::new();
+    for (i, (line, tokens)) in lines.iter().zip(tokens).enumerate() {
+        let row = format!("{:>gutter$} | {:width$} | {}", i + 1, line, tokens.join(" "), gutter = gutter, width = width);
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+    if !unplaced.is_empty() {
+        let row = format!("{:>gutter$} | {:width$} | {}", "-", "", unplaced.join(" "), gutter = gutter, width = width);
+        out.push_str(row.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Formats the tokens as JSON, including the text of `source` each was made from.
+pub fn dump_json(ast: &[Token], source: &str) -> String {
+    let tokens: Vec<String> = ast.iter().enumerate()
+        .map(|(i, token)| {
+            let target = match token.tk {
+                TokenType::LoopStart | TokenType::LoopEnd => token.value.to_string(),
+                _ => String::from("null"),
+            };
+            let (span, text) = match source.get(token.span.start..token.span.end) {
+                Some(x) if token.span.line > 0 => (
+                    format!(
+                        "{{\"start\": {}, \"end\": {}, \"line\": {}, \"col\": {}}}",
+                        token.span.start, token.span.end, token.span.line, token.span.col
+                    ),
+                    json::string(x),
+                ),
+                _ => (String::from("null"), String::from("null")),
+            };
+
+            format!(
+                "    {{\"index\": {}, \"type\": \"{:?}\", \"value\": {}, \"text\": {}, \"target\": {}, \"span\": {}, \"source\": {}}}",
+                i, token.tk, token.value, json::string(&token_text(token)), target, span, text
+            )
+        })
+        .collect();
+
+    format!("{{\n  \"tokens\": [\n{}\n  ]\n}}\n", tokens.join(",\n"))
+}
+
+/// Formats the program's control flow as a Graphviz graph.
+pub fn dump_dot(ast: &[Token]) -> String {
+    // Number the nodes: each loop bracket and the end get their own, and each straight run of
+    // other tokens shares one
+    let mut node_of = Vec::with_capacity(ast.len());
+    let mut nodes: Vec<(String, &str)> = Vec::new();
+    let mut run_start = 0;
+    for (i, token) in ast.iter().enumerate() {
+        let position = if token.span.line > 0 {
+            format!("\\n{}:{}", token.span.line, token.span.col)
+        } else {
+            String::new()
+        };
+
+        match token.tk {
+            TokenType::LoopStart | TokenType::LoopEnd => {
+                nodes.push((format!("{} #{}{}", token_text(token), i, position), "diamond"));
+            },
+            TokenType::End => {
+                nodes.push((String::from("end"), "doublecircle"));
+            },
+            _ => {
+                if i > 0 && !matches!(ast[i - 1].tk, TokenType::LoopStart | TokenType::LoopEnd) {
+                    // Keep long runs readable by wrapping them
+                    let separator = if (i - run_start) % DOT_TOKENS_PER_LINE == 0 { "\\n" } else { " " };
+                    nodes.last_mut().unwrap().0.push_str(&format!("{}{}", separator, token_text(token)));
+                    node_of.push(nodes.len() - 1);
+                    continue;
+                }
+                nodes.push((token_text(token), "box"));
+                run_start = i;
+            },
+        }
+        node_of.push(nodes.len() - 1);
+    }
+
+    let mut out = String::from("digraph program {\n    node [fontname=\"monospace\"];\n");
+    out.push_str("    start [shape=point];\n");
+    for (i, (label, shape)) in nodes.iter().enumerate() {
+        out.push_str(&format!("    n{} [shape={}, label=\"{}\"];\n", i, shape, label));
+    }
+
+    if let Some(first) = node_of.first() {
+        out.push_str(&format!("    start -> n{};\n", first));
+    }
+    for (i, token) in ast.iter().enumerate() {
+        let next = match node_of.get(i + 1) {
+            Some(x) => *x,
+            None => break,
+        };
+        let node = node_of[i];
+
+        match token.tk {
+            TokenType::LoopStart => {
+                out.push_str(&format!("    n{} -> n{} [label=\"nonzero\"];\n", node, next));
+                if let Some(after) = node_of.get(token.value as usize + 1) {
+                    out.push_str(&format!("    n{} -> n{} [label=\"zero\"];\n", node, after));
+                }
+            },
+            TokenType::LoopEnd => {
+                out.push_str(&format!("    n{} -> n{} [label=\"zero\"];\n", node, next));
+                if let Some(after) = node_of.get(token.value as usize + 1) {
+                    out.push_str(&format!("    n{} -> n{} [label=\"nonzero\"];\n", node, after));
+                }
+            },
+            _ if next != node => {
+                out.push_str(&format!("    n{} -> n{};\n", node, next));
+            },
+            _ => {},
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Formats `source` as a web page, marking the text each token was made from.
+pub fn dump_html(ast: &[Token], source: &str) -> String {
+    let mut out = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rustfuck dump</title>\n<style>\n",
+        "pre { line-height: 1.6; }\n",
+        ".comment { color: #888; }\n",
+        ".token { background: #eef; border-radius: 2px; }\n",
+        ".optimized { background: #efe; }\n",
+        ".ir { color: #080; font-size: 75%; vertical-align: super; }\n",
+        "</style>\n</head>\n<body>\n<pre>",
+    ));
+
+    let mut cursor = 0;
+    for (i, token) in ast.iter().enumerate() {
+        let text = match source.get(token.span.start..token.span.end) {
+            Some(x) if token.span.line > 0 && token.span.start >= cursor && !x.is_empty() => x,
+            _ => continue,
+        };
+
+        if token.span.start > cursor {
+            out.push_str(&format!("<span class=\"comment\">{}</span>", html_escape(&source[cursor..token.span.start])));
+        }
+
+        // Only tokens which don't look like the code they replaced are labelled
+        let ir = token_text(token);
+        let optimized = ir != text;
+        out.push_str(&format!(
+            "<span class=\"{}\" title=\"#{} {}\">{}</span>",
+            if optimized { "token optimized" } else { "token" },
+            i, html_escape(&ir), html_escape(text)
+        ));
+        if optimized {
+            out.push_str(&format!("<span class=\"ir\">{}</span>", html_escape(&ir)));
+        }
+        cursor = token.span.end;
+    }
+    if cursor < source.len() {
+        out.push_str(&format!("<span class=\"comment\">{}</span>", html_escape(&source[cursor..])));
+    }
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+/// Escapes text for use in HTML.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{self, OptConfig};
+    use crate::parse::{self, ParseConfig};
+
+    const SOURCE: &str = "+++ set\n[->+<]\t.";
+
+    fn parsed(source: &str) -> Ast {
+        let mut ast = parse::parse(source, &ParseConfig::default()).unwrap();
+        parse::link_loops(&mut ast).unwrap();
+        ast
+    }
+
+    fn optimized(source: &str) -> Ast {
+        let mut ast = parsed(source);
+        optimize::optimize(&mut ast, &OptConfig::default());
+        parse::link_loops(&mut ast).unwrap();
+        ast
+    }
+
+    #[test]
+    fn annotates_tokens_with_their_source() {
+        let expected = "\
+INDEX  TOKEN  LINE:COL   SOURCE
+    0  +3     1:1        +++
+    1  M1     2:1        [->+<
+    2  S0     2:6        ]
+    3  .      2:8        .
+    4  :      2:9
+";
+        assert_eq!(dump_annotated(&optimized(SOURCE), SOURCE), expected);
+    }
+
+    #[test]
+    fn indents_annotated_loops() {
+        let expected = "\
+INDEX  TOKEN    LINE:COL   SOURCE
+    0  [        1:1        [
+    1    [      1:2        [
+    2      [    1:3        [
+    3        ,  1:4        ,
+    4      ]    1:5        ]
+    5    ]      1:6        ]
+    6  ]        1:7        ]
+    7  :        1:8
+";
+        assert_eq!(dump_annotated(&parsed("[[[,]]]"), "[[[,]]]"), expected);
+    }
+
+    #[test]
+    fn annotates_tokens_without_a_source() {
+        let ast = vec![Token::new(TokenType::Set, 5), Token::new(TokenType::End, 0)];
+        assert_eq!(dump_annotated(&ast, ""), "INDEX  TOKEN  LINE:COL   SOURCE\n    0  S5     -\n    1  :      -\n");
+    }
+
+    #[test]
+    fn annotates_a_stray_loop_end() {
+        let ast = vec![Token::new(TokenType::LoopEnd, 0), Token::new(TokenType::End, 0)];
+        assert_eq!(dump_annotated(&ast, ""), "INDEX  TOKEN  LINE:COL   SOURCE\n    0  ]      -\n    1  :      -\n");
+    }
+
+    #[test]
+    fn clips_long_source() {
+        let source = format!("+ {}", "-".repeat(60));
+        let ast = optimized(&source);
+        let expected = format!("    1  -60    1:3        {}...\n", "-".repeat(SOURCE_WIDTH - 3));
+        assert!(dump_annotated(&ast, &source).contains(&expected));
+    }
+
+    #[test]
+    fn shows_source_lines_beside_their_tokens() {
+        let expected = "\
+1 | +++ set  | +3
+2 | [->+<] . | M1 S0 .
+";
+        assert_eq!(dump_side_by_side(&optimized(SOURCE), SOURCE), expected);
+
+        // Tokens with no position are listed at the end
+        let mut ast = parsed("+\n\n-");
+        ast.insert(1, Token::new(TokenType::Set, 0));
+        assert_eq!(dump_side_by_side(&ast, "+\n\n-"), "1 | + | +\n2 |   |\n3 | - | -\n- |   | S0\n");
+    }
+
+    #[test]
+    fn parses_dump_views() {
+        assert_eq!("tokens".parse(), Ok(DumpView::Tokens));
+        assert_eq!("annotated".parse(), Ok(DumpView::Annotated));
+        assert_eq!("side-by-side".parse(), Ok(DumpView::SideBySide));
+        assert_eq!("sideways".parse::<DumpView>(), Err("Unknown dump view"));
+    }
+
+    #[test]
+    fn dumps_json_with_targets_and_spans() {
+        let json = dump_json(&parsed("[\"]"), "[\"]");
+        assert!(json.starts_with("{\n  \"tokens\": [\n"));
+        assert!(json.contains(
+            "{\"index\": 0, \"type\": \"LoopStart\", \"value\": 1, \"text\": \"[\", \"target\": 1, \
+             \"span\": {\"start\": 0, \"end\": 1, \"line\": 1, \"col\": 1}, \"source\": \"[\"}"
+        ));
+        assert!(json.contains("\"target\": 0, \"span\": {\"start\": 2, \"end\": 3, \"line\": 1, \"col\": 3}"));
+
+        let json = dump_json(&[Token::new(TokenType::Set, 0)], "");
+        assert!(json.contains("\"text\": \"S0\", \"target\": null, \"span\": null, \"source\": null}"));
+    }
+
+    #[test]
+    fn dumps_control_flow_for_graphviz() {
+        let expected = "\
+digraph program {
+    node [fontname=\"monospace\"];
+    start [shape=point];
+    n0 [shape=box, label=\"+\"];
+    n1 [shape=diamond, label=\"[ #1\\n1:2\"];
+    n2 [shape=box, label=\"- . >\"];
+    n3 [shape=diamond, label=\"] #5\\n1:6\"];
+    n4 [shape=box, label=\"<\"];
+    n5 [shape=doublecircle, label=\"end\"];
+    start -> n0;
+    n0 -> n1;
+    n1 -> n2 [label=\"nonzero\"];
+    n1 -> n4 [label=\"zero\"];
+    n2 -> n3;
+    n3 -> n4 [label=\"zero\"];
+    n3 -> n2 [label=\"nonzero\"];
+    n4 -> n5;
+}
+";
+        assert_eq!(dump_dot(&optimized("+[-.>]<")), expected);
+    }
+
+    #[test]
+    fn wraps_long_runs_in_graphviz_nodes() {
+        let dot = dump_dot(&parsed(&format!(",[{}]", "+-".repeat(7))));
+        let first_line = ["+", "-"].repeat(6).join(" ");
+        assert!(dot.contains(&format!("n2 [shape=box, label=\"{}\\n+ -\"];", first_line)));
+    }
+
+    #[test]
+    fn marks_source_in_html() {
+        let source = "a <++> b\n[-]";
+        let html = dump_html(&optimized(source), source);
+        let expected = concat!(
+            "<pre><span class=\"comment\">a </span>",
+            "<span class=\"token\" title=\"#0 &lt;\">&lt;</span>",
+            "<span class=\"token optimized\" title=\"#1 +2\">++</span><span class=\"ir\">+2</span>",
+            "<span class=\"token\" title=\"#2 &gt;\">&gt;</span>",
+            "<span class=\"comment\"> b\n</span>",
+            "<span class=\"token optimized\" title=\"#3 S0\">[-]</span><span class=\"ir\">S0</span></pre>\n",
+        );
+
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains(expected));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn parses_dump_formats() {
+        assert_eq!("text".parse(), Ok(AstFormat::Text));
+        assert_eq!("json".parse(), Ok(AstFormat::Json));
+        assert_eq!("dot".parse(), Ok(AstFormat::Dot));
+        assert_eq!("html".parse(), Ok(AstFormat::Html));
+        assert_eq!("xml".parse::<AstFormat>(), Err("Unknown dump format"));
+    }
+
+    #[test]
+    fn dumps_tokens_after_the_last_loop() {
+        assert_eq!(parsed("+[-]>+.").dump(), "+ \n[ \n  - \n] \n> + . : \n");
+    }
+}
@@ -0,0 +1,395 @@
+//! `rustfuck batch`, which runs every program in a directory and reports on them.
+
+use argparse::{ArgumentParser, Store, StoreFalse, StoreTrue, StoreOption};
+
+use rustfuck::{Program, OptConfig, ParseConfig, RunConfig, CellSize, Drive, Error, Tape};
+use rustfuck::cell_size::CellWidth;
+use rustfuck::json;
+use rustfuck::stream::{Flush, Input, IoMode, Output, StreamIo};
+
+use std::{env, fs, io, panic, process, thread};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How many steps are run between checks of the time limit.
+const CHUNK_STEPS: u64 = 65536;
+
+/// Settings shared by every program in the batch.
+struct BatchOptions {
+    config: RunConfig,
+    parse: ParseConfig,
+    cell_size: CellWidth,
+    mode: IoMode,
+    optimize: bool,
+    max_steps: Option<u64>,
+    timeout: Option<u64>,
+    max_output: Option<usize>,
+}
+
+/// The outcome of running one program.
+struct Report {
+    name: String,
+    steps: u64,
+    time: Duration,
+    output: Vec<u8>,
+    result: Result<(), Error>,
+}
+
+/// Runs the program in chunks of steps, enforcing the step limit exactly and checking the time
+/// limit between chunks. The end of the program isn't a step, so `max_steps` allows that many
+/// instructions.
+fn run_program<T>(program: &Program, options: &BatchOptions, input: &[u8], output: &mut Vec<u8>, steps: &mut u64) -> Result<(), Error>
+where T: CellSize + Clone + Copy
+{
+    let mut interpreter = program.interpreter::<T>(&options.config, Tape::default())?;
+    let output = Output::new(output, Flush::Exit).with_limit(options.max_output);
+    let mut io = StreamIo::new(Input::new(input, None, false), output, options.mode);
+    let start = Instant::now();
+
+    loop {
+        let chunk = match options.max_steps {
+            Some(max_steps) => CHUNK_STEPS.min(max_steps - *steps),
+            None => CHUNK_STEPS,
+        };
+        let (drive, ran) = rustfuck::drive_for(&mut interpreter, &mut io, chunk as usize)?;
+        *steps += ran as u64;
+
+        if let Drive::Halted = drive {
+            return Ok(());
+        }
+        if let Some(max_steps) = options.max_steps {
+            if *steps >= max_steps {
+                return Err(Error::StepLimit(max_steps));
+            }
+        }
+        if let Some(timeout) = options.timeout {
+            if start.elapsed() >= Duration::from_millis(timeout) {
+                return Err(Error::Timeout(timeout));
+            }
+        }
+    }
+}
+
+/// Parses and runs the program at `path`, with input from the matching `.in` file if there is one.
+fn run_file(path: &Path, options: &BatchOptions, output: &mut Vec<u8>, steps: &mut u64) -> Result<(), Error> {
+    let source = match fs::read(path) {
+        Ok(x) => x,
+        Err(x) => return Err(Error::Io("Could not open file", x)),
+    };
+    let input = match fs::read(path.with_extension("in")) {
+        Ok(x) => x,
+        Err(x) if x.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(x) => return Err(Error::Io("Could not open input file", x)),
+    };
+
+    let mut program = Program::parse_with(&String::from_utf8_lossy(&source), &options.parse)?;
+    if options.optimize {
+        program.optimize(&OptConfig::default());
+    }
+
+    match options.cell_size {
+        CellWidth::Bits(8) => run_program::<u8>(&program, options, &input, output, steps),
+        CellWidth::Bits(16) => run_program::<u16>(&program, options, &input, output, steps),
+        CellWidth::Bits(32) => run_program::<u32>(&program, options, &input, output, steps),
+        CellWidth::Bits(64) => run_program::<u64>(&program, options, &input, output, steps),
+        CellWidth::Bits(bits) => Err(Error::UnsupportedCellSize(bits)),
+        CellWidth::Auto => Err(Error::Config("Cell size auto isn't supported by batch")),
+    }
+}
+
+/// Runs the program at `path` and records how it went.
+fn report(path: &Path, options: &BatchOptions) -> Report {
+    report_with(path, options, run_file)
+}
+
+/// Records how `run` went on the program at `path`. A panic is recorded as the program's failure,
+/// rather than taking the rest of the batch down with it.
+fn report_with<F>(path: &Path, options: &BatchOptions, run: F) -> Report
+where F: FnOnce(&Path, &BatchOptions, &mut Vec<u8>, &mut u64) -> Result<(), Error>
+{
+    let mut output = Vec::new();
+    let mut steps = 0;
+
+    let start = Instant::now();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| run(path, options, &mut output, &mut steps)))
+        .unwrap_or_else(|payload| Err(Error::from_panic(payload)));
+
+    Report {
+        name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        steps,
+        time: start.elapsed(),
+        output,
+        result,
+    }
+}
+
+/// Runs every `.b` file in `dir` on `jobs` worker threads, returning reports in name order.
+fn run_all(dir: &str, options: &BatchOptions, jobs: usize) -> Result<Vec<Report>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(x) => return Err(Error::Io("Could not read batch directory", x)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "b"))
+        .collect();
+    paths.sort();
+
+    let next = AtomicUsize::new(0);
+    let reports: Mutex<Vec<Option<Report>>> = Mutex::new(paths.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(paths.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let path = match paths.get(i) {
+                        Some(x) => x,
+                        None => break,
+                    };
+
+                    let report = report(path, options);
+                    reports.lock().unwrap()[i] = Some(report);
+                }
+            });
+        }
+    });
+
+    Ok(reports.into_inner().unwrap().into_iter().flatten().collect())
+}
+
+/// A short description of how the program finished.
+fn status(report: &Report) -> String {
+    match &report.result {
+        Ok(_) => String::from("ok"),
+        Err(x) => x.to_string(),
+    }
+}
+
+/// Formats the reports as an aligned table.
+fn summary(reports: &[Report]) -> String {
+    let width = reports.iter()
+        .map(|r| r.name.len())
+        .chain(Some("PROGRAM".len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut out = format!("{:width$}  {:>12}  {:>10}  {:>8}  STATUS\n", "PROGRAM", "STEPS", "TIME (ms)", "OUTPUT", width = width);
+    for report in reports {
+        out.push_str(&format!(
+            "{:width$}  {:>12}  {:>10.3}  {:>8}  {}\n",
+            report.name,
+            report.steps,
+            report.time.as_secs_f64() * 1000.0,
+            report.output.len(),
+            status(report),
+            width = width
+        ));
+    }
+
+    let passed = reports.iter().filter(|r| r.result.is_ok()).count();
+    out.push_str(&format!("\n{} of {} programs finished successfully\n", passed, reports.len()));
+    out
+}
+
+/// Formats the reports as JSON. Output which isn't valid UTF-8 is converted lossily.
+fn json_report(reports: &[Report]) -> String {
+    let programs: Vec<String> = reports.iter()
+        .map(|report| {
+            let (error, exit_code) = match &report.result {
+                Ok(_) => (String::from("null"), 0),
+                Err(x) => (json::string(&x.to_string()), x.exit_code()),
+            };
+
+            format!(
+                "    {{\"name\": {}, \"ok\": {}, \"exit_code\": {}, \"error\": {}, \"steps\": {}, \"time_ms\": {:.3}, \"output\": {}}}",
+                json::string(&report.name),
+                report.result.is_ok(),
+                exit_code,
+                error,
+                report.steps,
+                report.time.as_secs_f64() * 1000.0,
+                json::string(&String::from_utf8_lossy(&report.output))
+            )
+        })
+        .collect();
+
+    format!("{{\n  \"programs\": [\n{}\n  ]\n}}\n", programs.join(",\n"))
+}
+
+/// Parses the batch command line, runs the programs and writes the reports.
+///
+/// Exits with the exit code of the first program (in name order) which failed, or 0 if they all
+/// finished successfully.
+pub fn main() -> Result<(), Error> {
+    let mut dir = String::new();
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut report_file: Option<String> = None;
+    let mut options = BatchOptions {
+        config: RunConfig::default(),
+        parse: ParseConfig::default(),
+        cell_size: CellWidth::Bits(8),
+        mode: IoMode::Bytes,
+        optimize: true,
+        max_steps: None,
+        timeout: None,
+        max_output: None,
+    };
+
+    {  // this block limits scope of borrows by ap.refer() method
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Run every .b file in a directory. Each program reads its input from the file with the same name and an .in extension, if there is one.");
+        ap.refer(&mut dir)
+            .add_argument("dir", Store, "Directory containing the programs to run")
+            .required();
+        ap.refer(&mut jobs)
+            .add_option(&["-j", "--jobs"], Store, "Number of programs to run at once. Default the number of CPUs.");
+        ap.refer(&mut options.max_steps)
+            .add_option(&["--max-steps"], StoreOption, "Stop each program with an error once it has run this many steps (instructions of the optimized program) without finishing.");
+        ap.refer(&mut options.timeout)
+            .add_option(&["--timeout"], StoreOption, "Stop each program with an error after this many milliseconds.");
+        ap.refer(&mut options.max_output)
+            .add_option(&["--max-output"], StoreOption, "Stop each program with an error once it has written this many bytes.");
+        ap.refer(&mut report_file)
+            .add_option(&["--report"], StoreOption, "File to write a JSON report to, including each program's output.");
+        ap.refer(&mut options.optimize)
+            .add_option(&["--no-optimize"], StoreFalse, "Don't optimize code");
+        ap.refer(&mut options.parse.strict)
+            .add_option(&["--strict"], StoreTrue, "Reject characters other than commands and whitespace which aren't in a comment.");
+        ap.refer(&mut options.parse.hash_comments)
+            .add_option(&["--hash-comments"], StoreTrue, "Treat # and the rest of its line as a comment.");
+        ap.refer(&mut options.parse.comment_block)
+            .add_option(&["--comment-block"], StoreTrue, "Treat a loop at the very start of each program as a comment.");
+        ap.refer(&mut options.cell_size)
+            .add_option(&["-s", "--cell-size"], Store, "Size of each cell in bits. Accepted values: 8, 16, 32, 64. Default 8.");
+        ap.refer(&mut options.config.tape_size)
+            .add_option(&["-t", "--tape-size"], Store, "Size of the data tape. Default 30000.");
+        ap.refer(&mut options.config.eof)
+            .add_option(&["--eof"], Store, "Value stored by , at the end of input. Accepted values: zero, minus-one (all bits set), unchanged. Default zero.");
+        ap.refer(&mut options.mode)
+            .add_option(&["--io"], Store, "How cells are read and written. Accepted values: bytes, utf8, decimal. Default bytes.");
+
+        let args = env::args().take(1).map(|name| name + " batch")
+            .chain(env::args().skip(2))
+            .collect();
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+
+    let reports = run_all(&dir, &options, jobs)?;
+    print!("{}", summary(&reports));
+
+    if let Some(filename) = report_file {
+        if let Err(x) = fs::write(filename, json_report(&reports)) {
+            return Err(Error::Io("Could not write batch report", x));
+        }
+    }
+
+    if let Some(Err(x)) = reports.iter().map(|r| &r.result).find(|r| r.is_err()) {
+        process::exit(x.exit_code());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::temp_dir;
+
+    fn options() -> BatchOptions {
+        BatchOptions {
+            config: RunConfig::default(),
+            parse: ParseConfig::default(),
+            cell_size: CellWidth::Bits(8),
+            mode: IoMode::Bytes,
+            optimize: true,
+            max_steps: Some(10000),
+            timeout: None,
+            max_output: None,
+        }
+    }
+
+    /// Writes the programs and inputs to a fresh directory and runs them all.
+    fn run_files(name: &str, files: &[(&str, &str)], options: &BatchOptions) -> Vec<Report> {
+        let dir = temp_dir(name);
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+
+        run_all(dir.to_str().unwrap(), options, 2).unwrap()
+    }
+
+    #[test]
+    fn runs_every_program_with_its_input() {
+        let files = [
+            ("echo.b", ",[.,]"),
+            ("echo.in", "hi"),
+            ("loop.b", "+[]"),
+            ("broken.b", "[[]"),
+            ("notes.txt", "not a program"),
+        ];
+        let reports = run_files("every", &files, &options());
+
+        let names: Vec<&str> = reports.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["broken.b", "echo.b", "loop.b"]);
+
+        assert!(matches!(reports[0].result, Err(Error::Syntax(_))));
+        assert!(reports[1].result.is_ok());
+        assert_eq!(reports[1].output, b"hi");
+        assert!(matches!(reports[2].result, Err(Error::StepLimit(10000))));
+        assert_eq!(reports[2].steps, 10000);
+    }
+
+    #[test]
+    fn allows_exactly_max_steps() {
+        let options = BatchOptions { max_steps: Some(3), optimize: false, ..options() };
+        let reports = run_files("exact", &[("fits.b", "+>."), ("over.b", "+>.+")], &options);
+
+        assert!(reports[0].result.is_ok());
+        assert_eq!(reports[0].steps, 3);
+        assert!(matches!(reports[1].result, Err(Error::StepLimit(3))));
+        assert_eq!(reports[1].steps, 3);
+    }
+
+    #[test]
+    fn records_a_panic_as_the_program_failing() {
+        let report = report_with(Path::new("dir/bug.b"), &options(), |_, _, output, steps| {
+            output.push(b'x');
+            *steps = 2;
+            panic!("broken");
+        });
+
+        assert_eq!(report.name, "bug.b");
+        assert_eq!(report.output, b"x");
+        assert_eq!(status(&report), "Internal error: broken");
+        assert!(json_report(&[report]).contains("\"ok\": false, \"exit_code\": 101, \"error\": \"Internal error: broken\""));
+    }
+
+    #[test]
+    fn stops_programs_at_the_output_limit() {
+        let options = BatchOptions { max_output: Some(3), ..options() };
+        let reports = run_files("output", &[("many.b", "+[.]")], &options);
+
+        assert!(matches!(reports[0].result, Err(Error::OutputLimit)));
+        assert_eq!(reports[0].output, [1, 1, 1]);
+    }
+
+    #[test]
+    fn reports_as_a_table_and_json() {
+        let reports = run_files("reports", &[("a.b", "+++."), ("b.b", "<")], &options());
+
+        let table = summary(&reports);
+        assert!(table.starts_with("PROGRAM"));
+        assert!(table.ends_with("1 of 2 programs finished successfully\n"));
+
+        let json = json_report(&reports);
+        assert!(json.contains("\"name\": \"a.b\", \"ok\": true, \"exit_code\": 0, \"error\": null,"));
+        assert!(json.contains("\"name\": \"b.b\", \"ok\": false, \"exit_code\": 4,"));
+        assert!(json.contains("\"output\": \"\\u0003\"}"));
+    }
+}
@@ -1,10 +1,37 @@
 
-use std::{iter};
+use std::{iter, str, io};
+use std::convert::TryFrom;
+use io::{Read, Write};
+
+use crate::error::Error;
+use crate::stream::{self, IoMode, Output};
+
+/// The cell size requested on the command line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CellWidth {
+    /// A fixed number of bits per cell.
+    Bits(u8),
+    /// Detect whether the program needs 8-bit wrapping or wider cells while it runs.
+    Auto,
+}
+
+impl str::FromStr for CellWidth {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<CellWidth, &'static str> {
+        match s {
+            "auto" => Ok(CellWidth::Auto),
+            _ => match s.parse() {
+                Ok(bits) => Ok(CellWidth::Bits(bits)),
+                Err(_) => Err("Invalid cell size"),
+            },
+        }
+    }
+}
 
 pub trait CellSize {
-    fn get_zeroes(count: usize) -> iter::Take<iter::Repeat<Self>>
-    where Self: Sized,
-          iter::Repeat<Self>: Iterator;
+    fn get_zeroes(count: usize) -> iter::RepeatN<Self>
+    where Self: Sized;
 
     fn add_to_cell(&mut self, value: Self);
     fn sub_from_cell(&mut self, value: Self);
@@ -16,11 +43,66 @@ pub trait CellSize {
     fn to_stdout(&self) -> u8 where Self: Sized;
 
     fn from_tk_value(v: i32) -> Self where Self: Sized;
+
+    fn from_u64(v: u64) -> Self where Self: Sized;
+    fn to_u64(&self) -> u64;
+
+    /// Reads a cell value from the input for `,`, or `None` at the end of the input.
+    fn read_cell<R: Read>(input: &mut R, mode: IoMode) -> Result<Option<Self>, Error>
+    where Self: Sized
+    {
+        let value = match mode {
+            IoMode::Bytes => stream::read_byte(input)?.map(u64::from),
+            IoMode::Utf8 => stream::read_char(input)?.map(u64::from),
+            IoMode::Decimal => stream::read_number(input)?,
+        };
+
+        match value {
+            Some(v) => {
+                let cell = Self::from_u64(v);
+                if cell.to_u64() != v {
+                    return Err(Error::InvalidData("Input value is too large for the cell size"));
+                }
+                Ok(Some(cell))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the cell value to the output `count` times for `.`.
+    fn write_cell<W: Write>(&self, output: &mut Output<W>, mode: IoMode, count: usize) -> Result<(), Error>
+    where Self: Sized
+    {
+        let mut buf = [0; 4];
+        let text;
+        let bytes: &[u8] = match mode {
+            IoMode::Bytes => {
+                buf[0] = self.to_stdout();
+                &buf[..1]
+            },
+            IoMode::Utf8 => {
+                let c = u32::try_from(self.to_u64()).ok().and_then(char::from_u32);
+                match c {
+                    Some(c) => c.encode_utf8(&mut buf).as_bytes(),
+                    None => return Err(Error::InvalidData("Cell value is not a valid Unicode scalar value")),
+                }
+            },
+            IoMode::Decimal => {
+                text = format!("{}\n", self.to_u64());
+                text.as_bytes()
+            },
+        };
+
+        match output.write_bytes(bytes, count) {
+            Ok(_) => Ok(()),
+            Err(x) => Err(Error::Io("Could not write output", x)),
+        }
+    }
 }
 
 impl CellSize for u8 {
-    fn get_zeroes(count: usize) -> iter::Take<iter::Repeat<u8>> {
-        iter::repeat(0).take(count)
+    fn get_zeroes(count: usize) -> iter::RepeatN<u8> {
+        iter::repeat_n(0, count)
     }
 
     fn add_to_cell(&mut self, value: u8) {
@@ -43,11 +125,14 @@ impl CellSize for u8 {
     fn to_stdout(&self) -> u8 { *self }
 
     fn from_tk_value(v: i32) -> u8 { v as u8 }
+
+    fn from_u64(v: u64) -> u8 { v as u8 }
+    fn to_u64(&self) -> u64 { *self as u64 }
 }
 
 impl CellSize for u16 {
-    fn get_zeroes(count: usize) -> iter::Take<iter::Repeat<u16>> {
-        iter::repeat(0).take(count)
+    fn get_zeroes(count: usize) -> iter::RepeatN<u16> {
+        iter::repeat_n(0, count)
     }
 
     fn add_to_cell(&mut self, value: u16) {
@@ -70,11 +155,14 @@ impl CellSize for u16 {
     fn to_stdout(&self) -> u8 { *self as u8 }
 
     fn from_tk_value(v: i32) -> u16 { v as u16 }
+
+    fn from_u64(v: u64) -> u16 { v as u16 }
+    fn to_u64(&self) -> u64 { *self as u64 }
 }
 
 impl CellSize for u32 {
-    fn get_zeroes(count: usize) -> iter::Take<iter::Repeat<u32>> {
-        iter::repeat(0).take(count)
+    fn get_zeroes(count: usize) -> iter::RepeatN<u32> {
+        iter::repeat_n(0, count)
     }
 
     fn add_to_cell(&mut self, value: u32) {
@@ -97,11 +185,14 @@ impl CellSize for u32 {
     fn to_stdout(&self) -> u8 { *self as u8 }
 
     fn from_tk_value(v: i32) -> u32 { v as u32 }
+
+    fn from_u64(v: u64) -> u32 { v as u32 }
+    fn to_u64(&self) -> u64 { *self as u64 }
 }
 
 impl CellSize for u64 {
-    fn get_zeroes(count: usize) -> iter::Take<iter::Repeat<u64>> {
-        iter::repeat(0).take(count)
+    fn get_zeroes(count: usize) -> iter::RepeatN<u64> {
+        iter::repeat_n(0, count)
     }
 
     fn add_to_cell(&mut self, value: u64) {
@@ -124,4 +215,7 @@ impl CellSize for u64 {
     fn to_stdout(&self) -> u8 { *self as u8 }
 
     fn from_tk_value(v: i32) -> u64 { v as u64 }
+
+    fn from_u64(v: u64) -> u64 { v }
+    fn to_u64(&self) -> u64 { *self }
 }
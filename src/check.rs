@@ -0,0 +1,74 @@
+//! `rustfuck check`, which looks for suspicious code without running it.
+
+use argparse::{ArgumentParser, List, Store, StoreOption, StoreTrue};
+
+use rustfuck::{Error, ParseConfig};
+use rustfuck::diagnostic::{ColorMode, Severity};
+use rustfuck::lint;
+
+use std::{env, fs, io, process};
+
+/// Parses the check command line, checks each file and prints what was found.
+///
+/// Fails with `Error::Syntax` if any file has unmatched brackets or stray characters, or `Error::Lint` if there were
+/// only warnings.
+pub fn main() -> Result<(), Error> {
+    let mut filenames: Vec<String> = Vec::new();
+    let mut color = ColorMode::Auto;
+    let mut parse_config = ParseConfig::default();
+
+    {  // this block limits scope of borrows by ap.refer() method
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Check brainfuck code for mistakes without running it. Warns about commands which cancel out, moving left of the first cell, empty and unbalanced loops, and code after a loop which never ends.");
+        ap.refer(&mut filenames)
+            .add_argument("filenames", List, "Files containing brainfuck code")
+            .required();
+        ap.refer(&mut color)
+            .add_option(&["--color"], Store, "Whether messages are colored. Accepted values: auto, always, never. Default auto.");
+        ap.refer(&mut parse_config.strict)
+            .add_option(&["--strict"], StoreTrue, "Report characters other than commands and whitespace which aren't in a comment.");
+        ap.refer(&mut parse_config.hash_comments)
+            .add_option(&["--hash-comments"], StoreTrue, "Treat # and the rest of its line as a comment.");
+        ap.refer(&mut parse_config.debug_char)
+            .add_option(&["--debug-char"], StoreOption, "Character which prints the tape when run, which isn't reported by --strict.");
+        ap.refer(&mut parse_config.comment_block)
+            .add_option(&["--comment-block"], StoreTrue, "Treat a loop at the very start of the program as a comment.");
+
+        let args = env::args().take(1).map(|name| name + " check")
+            .chain(env::args().skip(2))
+            .collect();
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+
+    let color = color.use_color();
+    let mut errors = Vec::new();
+    let mut warnings = 0;
+
+    for filename in &filenames {
+        let source = match fs::read(filename) {
+            Ok(x) => String::from_utf8_lossy(&x).into_owned(),
+            Err(x) => return Err(Error::Io("Could not open file", x)),
+        };
+
+        for diagnostic in lint::check(&source, &parse_config)? {
+            eprintln!("{}", diagnostic.render(&source, filename, color));
+            match diagnostic.severity {
+                Severity::Error => errors.push(diagnostic),
+                Severity::Warning => warnings += 1,
+            }
+        }
+    }
+
+    let plural = |n: usize, word: &str| if n == 1 { format!("1 {}", word) } else { format!("{} {}s", n, word) };
+    eprintln!("{} and {} found", plural(errors.len(), "error"), plural(warnings, "warning"));
+
+    if !errors.is_empty() {
+        Err(Error::Syntax(errors))
+    } else if warnings > 0 {
+        Err(Error::Lint(warnings))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,521 @@
+//! `--debug`, an interactive prompt for stepping through a program.
+
+use rustfuck::{Program, RunConfig, CellSize, Drive, Error, Interpreter, Tape, Token, TokenType};
+use rustfuck::diagnostic::Diagnostic;
+use rustfuck::stream::BfIo;
+
+use std::io::{self, Write};
+
+const HELP: &str = "\
+Commands:
+  step [N], s [N]        Run the next token, or the next N tokens
+  next, n                Like step, but runs a whole loop at once
+  continue, c            Run until a breakpoint is reached or the program ends
+  break LOCATION, b      Stop before running the code at LOCATION, which is LINE, LINE:COL or #INDEX
+  delete [N], d [N]      Delete breakpoint N, or every breakpoint
+  info, i                List the breakpoints
+  print tape[A..B], p    Show the cells from A up to B; also tape[A], dp and ip
+  set cell N V           Change the value of cell N to V
+  where, w               Show the instruction pointer, data pointer and source position
+  quit, q                Stop debugging";
+
+/// A place where execution stops before running a token.
+struct Breakpoint {
+    id: usize,
+    index: usize,
+}
+
+/// The debugger's state while a program is being stepped through.
+struct Debugger<'a, T, I> {
+    interpreter: Interpreter<'a, T>,
+    tokens: &'a [Token],
+    source: &'a str,
+    io: &'a mut I,
+    tape_size: usize,
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    /// How the program ended, once it can't run any further.
+    outcome: Option<Result<(), Error>>,
+}
+
+/// Finds the byte offset of a line and column in `source`, counted the same way as the parser.
+pub fn offset_of(source: &str, line: usize, col: usize) -> Option<usize> {
+    let mut current = (1, 1);
+    for (i, c) in source.char_indices() {
+        if current == (line, col) || current.0 > line {
+            return Some(i);
+        }
+
+        if c == '\n' {
+            current = (current.0 + 1, 1);
+        } else {
+            current.1 += 1;
+        }
+    }
+
+    None
+}
+
+/// Finds the token a location refers to: `#INDEX`, or `LINE` or `LINE:COL` in `source`. A position
+/// inside a token merged by the optimizer finds that token, and a position between tokens finds
+/// the next one.
+pub fn locate(source: &str, tokens: &[Token], location: &str) -> Result<usize, String> {
+    if let Some(index) = location.strip_prefix('#') {
+        return match index.parse::<usize>() {
+            Ok(x) if x < tokens.len() => Ok(x),
+            _ => Err(format!("There's no token {}.", location)),
+        };
+    }
+
+    let mut parts = location.splitn(2, ':');
+    let line = parts.next().and_then(|x| x.parse::<usize>().ok());
+    let col = match parts.next() {
+        Some(x) => x.parse::<usize>().ok(),
+        None => Some(1),
+    };
+    let (line, col) = match (line, col) {
+        (Some(line), Some(col)) => (line, col),
+        _ => return Err(format!("Unknown location {}. Use LINE, LINE:COL or #INDEX.", location)),
+    };
+
+    offset_of(source, line, col)
+        .and_then(|offset| tokens.iter().position(|t| t.span.line > 0 && t.tk != TokenType::End && t.span.end > offset))
+        .ok_or_else(|| format!("There's no code at or after {}.", location))
+}
+
+impl<'a, T, I> Debugger<'a, T, I>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    /// Runs the next token, and then keeps going until `pause` says to stop before one or the
+    /// program ends, doing any I/O the tokens need.
+    fn resume<F>(&mut self, mut pause: F)
+    where F: FnMut(&Interpreter<'a, T>, &[Breakpoint]) -> bool
+    {
+        if let Some(outcome) = &self.outcome {
+            match outcome {
+                Ok(_) => eprintln!("The program has finished."),
+                Err(x) => eprintln!("The program failed: {}", x),
+            }
+            return;
+        }
+
+        let Debugger { interpreter, io, breakpoints, .. } = self;
+        let mut first = true;
+        let result = rustfuck::drive(interpreter, *io, |interpreter| {
+            let run = first || !pause(interpreter, breakpoints);
+            first = false;
+            Ok(run)
+        });
+
+        match result {
+            Ok(Drive::Paused) => {},
+            Ok(Drive::Halted) => {
+                eprintln!("The program finished.");
+                self.outcome = Some(Ok(()));
+            },
+            Err(x) => {
+                eprintln!("The program failed: {}", x);
+                self.outcome = Some(Err(x));
+            },
+        }
+    }
+
+    /// Runs until `done` says to stop, the program ends, or a breakpoint is reached.
+    fn run_until<F>(&mut self, done: F)
+    where F: Fn(&Interpreter<'a, T>) -> bool
+    {
+        self.resume(|interpreter, breakpoints| {
+            if done(interpreter) {
+                return true;
+            }
+
+            let ip = interpreter.instruction_pointer();
+            match breakpoints.iter().find(|b| b.index == ip) {
+                Some(breakpoint) => {
+                    eprintln!("Breakpoint {} reached.", breakpoint.id);
+                    true
+                },
+                None => false,
+            }
+        });
+    }
+
+    /// Shows the instruction pointer, data pointer and the source code of the next token.
+    fn show_where(&self) {
+        let ip = self.interpreter.instruction_pointer();
+        let dp = self.interpreter.data_pointer();
+        let token = &self.tokens[ip];
+        let cell = self.interpreter.cells().get(dp).map_or(0, |c| c.to_u64());
+
+        if token.span.line == 0 {
+            eprintln!("#{} {}, dp {}, cell {}", ip, token, dp, cell);
+            return;
+        }
+        eprintln!("#{} {} at {}:{}, dp {}, cell {}", ip, token, token.span.line, token.span.col, dp, cell);
+
+        eprint!("{}", Diagnostic::warning("", token.span, "next").snippet(self.source, false));
+    }
+
+    fn add_breakpoint(&mut self, location: &str) -> Result<(), String> {
+        let index = locate(self.source, self.tokens, location)?;
+        let token = &self.tokens[index];
+
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id: self.next_id, index });
+        if token.span.line > 0 {
+            eprintln!("Breakpoint {} at #{} {} ({}:{}).", self.next_id, index, token, token.span.line, token.span.col);
+        } else {
+            eprintln!("Breakpoint {} at #{} {}.", self.next_id, index, token);
+        }
+
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, id: Option<&str>) -> Result<(), String> {
+        let id = match id {
+            Some(x) => x,
+            None => {
+                self.breakpoints.clear();
+                return Ok(());
+            },
+        };
+
+        match self.breakpoints.iter().position(|b| id.parse() == Ok(b.id)) {
+            Some(i) => {
+                self.breakpoints.remove(i);
+                Ok(())
+            },
+            None => Err(format!("There's no breakpoint {}.", id)),
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            eprintln!("No breakpoints.");
+        }
+        for breakpoint in &self.breakpoints {
+            let span = self.tokens[breakpoint.index].span;
+            eprintln!("{:>3}  #{:<6} {}:{}", breakpoint.id, breakpoint.index, span.line, span.col);
+        }
+    }
+
+    fn print(&self, what: &str) -> Result<(), String> {
+        let cells = self.interpreter.cells();
+        let dp = self.interpreter.data_pointer();
+        let cell = |i: usize| cells.get(i).map_or(0, |c| c.to_u64());
+
+        match what {
+            "dp" => eprintln!("dp = {}", dp),
+            "ip" => eprintln!("ip = {}", self.interpreter.instruction_pointer()),
+            _ => {
+                let range = what.strip_prefix("tape[")
+                    .and_then(|x| x.strip_suffix(']'))
+                    .ok_or_else(|| format!("Can't print {}. Use tape[A..B], tape[A], dp or ip.", what))?;
+
+                let bound = |x: &str| x.trim().parse::<usize>().map_err(|_| format!("Invalid cell index {}.", x));
+                let (start, end) = match range.split_once("..") {
+                    Some((start, end)) => (bound(start)?, bound(end)?),
+                    None => {
+                        let i = bound(range)?;
+                        (i, i.saturating_add(1))
+                    },
+                };
+                if start > end {
+                    return Err(format!("The range {}..{} is backwards.", start, end));
+                }
+                if start >= self.tape_size {
+                    return Err(format!("Cell {} is past the end of the tape, which has {} cells.", start, self.tape_size));
+                }
+                let end = end.min(self.tape_size);
+
+                // The current cell is shown in brackets
+                let values: Vec<String> = (start..end)
+                    .map(|i| if i == dp { format!("[{}]", cell(i)) } else { cell(i).to_string() })
+                    .collect();
+                eprintln!("tape[{}..{}] = {}", start, end, values.join(" "));
+            },
+        }
+
+        Ok(())
+    }
+
+    fn set_cell(&mut self, args: &[&str]) -> Result<(), String> {
+        let (index, value) = match args {
+            ["cell", index, value] => (index, value),
+            _ => return Err(String::from("Use set cell N V.")),
+        };
+
+        let index = index.parse::<usize>().map_err(|_| format!("Invalid cell index {}.", index))?;
+        // Negative values wrap around, as if the cell was decremented from zero
+        let value = match value.parse::<i64>() {
+            Ok(x) => x as u64,
+            Err(_) => value.parse::<u64>().map_err(|_| format!("Invalid cell value {}.", value))?,
+        };
+
+        match self.interpreter.set_cell(index, T::from_u64(value)) {
+            Ok(_) => Ok(()),
+            Err(Error::TapeOverflow { .. }) => Err(format!("Cell {} is past the end of the tape.", index)),
+            Err(x) => Err(x.to_string()),
+        }
+    }
+
+    /// Runs one command line. Returns false when the user quits.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+
+        match command {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(x) => x.parse::<usize>().map_err(|_| format!("Invalid step count {}.", x))?,
+                    None => 1,
+                };
+                if count > 0 {
+                    let mut left = count - 1;
+                    self.resume(|_, _| match left {
+                        0 => true,
+                        _ => {
+                            left -= 1;
+                            false
+                        },
+                    });
+                }
+                self.show_where();
+            },
+            "next" | "n" => {
+                let ip = self.interpreter.instruction_pointer();
+                let token = &self.tokens[ip];
+                if token.tk == TokenType::LoopStart {
+                    let after = token.value as usize + 1;
+                    self.run_until(|interpreter| interpreter.instruction_pointer() == after);
+                } else {
+                    self.resume(|_, _| true);
+                }
+                self.show_where();
+            },
+            "continue" | "c" => {
+                self.run_until(|_| false);
+                self.show_where();
+            },
+            "break" | "b" => match args.first() {
+                Some(location) => self.add_breakpoint(location)?,
+                None => return Err(String::from("Use break LINE, break LINE:COL or break #INDEX.")),
+            },
+            "delete" | "d" => self.delete_breakpoint(args.first().copied())?,
+            "info" | "i" => self.list_breakpoints(),
+            "print" | "p" => self.print(&args.concat())?,
+            "set" => self.set_cell(args)?,
+            "where" | "w" => self.show_where(),
+            "help" | "h" => eprintln!("{}", HELP),
+            "quit" | "q" => return Ok(false),
+            _ => return Err(format!("Unknown command {}. Type help for a list of commands.", command)),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Checks that nothing but the debugger's commands will be read from stdin: not the code, and not
+/// the program's input if it has a `,`. Sharing stdin would hand commands to the program, or its
+/// input to the prompt.
+pub fn check_stdin(program: &Program, code_from_stdin: bool, input_from_stdin: bool) -> Result<(), Error> {
+    if code_from_stdin {
+        return Err(Error::Config("--debug reads its commands from stdin, so the code can't be read from there"));
+    }
+    if input_from_stdin && program.tokens().iter().any(|t| t.tk == TokenType::In) {
+        return Err(Error::Config("--debug reads its commands from stdin, so a program which reads input needs --input, --input-string or --input-hex"));
+    }
+
+    Ok(())
+}
+
+/// Runs the program under the debugger, reading commands from stdin until the user quits or
+/// stdin ends. The program's own output goes where it would normally; the debugger writes to
+/// stderr.
+///
+/// Returns the tape as it was left, or the error the program failed with.
+pub fn run<T, I>(program: &Program, source: &str, config: &RunConfig, tape: Tape<T>, io: &mut I) -> Result<Tape<T>, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let mut debugger = Debugger {
+        interpreter: program.interpreter(config, tape)?,
+        tokens: program.tokens(),
+        source,
+        io,
+        tape_size: config.tape_size,
+        breakpoints: Vec::new(),
+        next_id: 0,
+        outcome: None,
+    };
+
+    eprintln!("Debugging {} tokens. Type help for a list of commands.", debugger.tokens.len());
+    debugger.show_where();
+
+    // An empty line repeats the last command, like in gdb
+    let mut last = String::new();
+    loop {
+        // Keep the program's output in step with the debugger's
+        if let Err(x) = BfIo::<T>::finish(debugger.io) {
+            eprintln!("{}", x);
+        }
+        eprint!("(rf) ");
+        let _ = io::stderr().flush();
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(x) => return Err(Error::Io("Could not read debugger command", x)),
+        }
+        if line.trim().is_empty() {
+            line = last.clone();
+        } else {
+            last = line.clone();
+        }
+
+        match debugger.command(&line) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(message) => eprintln!("{}", message),
+        }
+    }
+
+    match debugger.outcome {
+        Some(Err(x)) => Err(x),
+        _ => Ok(debugger.interpreter.into_tape()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustfuck::stream::MemoryIo;
+
+    fn debugger<'a>(program: &'a Program, source: &'a str, io: &'a mut MemoryIo<u8>) -> Debugger<'a, u8, MemoryIo<u8>> {
+        let config = RunConfig { tape_size: 8, ..RunConfig::default() };
+        Debugger {
+            interpreter: program.interpreter(&config, Tape::default()).unwrap(),
+            tokens: program.tokens(),
+            source,
+            io,
+            tape_size: config.tape_size,
+            breakpoints: Vec::new(),
+            next_id: 0,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn keeps_stdin_for_commands() {
+        let reads = Program::parse(",.").unwrap();
+        let writes = Program::parse("+.").unwrap();
+
+        assert!(check_stdin(&writes, false, true).is_ok());
+        assert!(check_stdin(&reads, false, false).is_ok());
+        assert!(matches!(check_stdin(&reads, false, true), Err(Error::Config(_))));
+        assert!(matches!(check_stdin(&writes, true, false), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn finds_offsets_of_lines_and_columns() {
+        let source = "+é\n\n>.";
+        assert_eq!(offset_of(source, 1, 1), Some(0));
+        assert_eq!(offset_of(source, 1, 2), Some(1));
+        assert_eq!(offset_of(source, 2, 1), Some(4));
+        assert_eq!(offset_of(source, 3, 2), Some(6));
+        // A column past the end of its line finds the start of the next line
+        assert_eq!(offset_of(source, 1, 9), Some(4));
+        assert_eq!(offset_of(source, 4, 1), None);
+    }
+
+    #[test]
+    fn locates_tokens() {
+        let source = "++\n[->+<]\n\n.";
+        let mut program = Program::parse(source).unwrap();
+        program.optimize(&rustfuck::OptConfig::default());
+        let tokens = program.tokens();
+
+        assert_eq!(locate(source, tokens, "#2"), Ok(2));
+        assert_eq!(locate(source, tokens, "#99"), Err(String::from("There's no token #99.")));
+        assert_eq!(locate(source, tokens, "2"), Ok(1));
+        // Inside the move, which the optimizer merged into one token
+        assert_eq!(locate(source, tokens, "2:3"), Ok(1));
+        // Blank lines find the next token
+        assert_eq!(locate(source, tokens, "3"), Ok(3));
+        assert_eq!(locate(source, tokens, "5"), Err(String::from("There's no code at or after 5.")));
+        assert_eq!(locate(source, tokens, "x:1"), Err(String::from("Unknown location x:1. Use LINE, LINE:COL or #INDEX.")));
+    }
+
+    #[test]
+    fn steps_over_loops_and_stops_at_breakpoints() {
+        let source = "+++[>++<-]>.\n+.";
+        let program = Program::parse(source).unwrap();
+        let mut io = MemoryIo::new(&[]);
+        let mut debugger = debugger(&program, source, &mut io);
+
+        assert_eq!(debugger.command("step 3"), Ok(true));
+        assert_eq!(debugger.interpreter.instruction_pointer(), 3);
+        assert_eq!(debugger.command("next"), Ok(true));
+        assert_eq!(debugger.interpreter.instruction_pointer(), 10);
+        assert_eq!(debugger.interpreter.cells()[1], 6);
+
+        assert_eq!(debugger.command("break 2"), Ok(true));
+        assert_eq!(debugger.command("c"), Ok(true));
+        assert_eq!(debugger.interpreter.instruction_pointer(), 12);
+
+        assert_eq!(debugger.command("delete 1"), Ok(true));
+        assert_eq!(debugger.command("delete 1"), Err(String::from("There's no breakpoint 1.")));
+        assert_eq!(debugger.command("continue"), Ok(true));
+        assert!(matches!(debugger.outcome, Some(Ok(()))));
+        assert_eq!(debugger.command("quit"), Ok(false));
+
+        drop(debugger);
+        assert_eq!(io.output, [6, 7]);
+    }
+
+    #[test]
+    fn sets_cells() {
+        let program = Program::parse(".").unwrap();
+        let mut io = MemoryIo::new(&[]);
+        let mut debugger = debugger(&program, ".", &mut io);
+
+        assert_eq!(debugger.command("set cell 0 -1"), Ok(true));
+        assert_eq!(debugger.interpreter.cells()[0], 255);
+        assert_eq!(debugger.command("set cell 8 1"), Err(String::from("Cell 8 is past the end of the tape.")));
+        assert_eq!(debugger.command("set cell 0 x"), Err(String::from("Invalid cell value x.")));
+        assert_eq!(debugger.command("set 0 1"), Err(String::from("Use set cell N V.")));
+    }
+
+    #[test]
+    fn prints_only_cells_on_the_tape() {
+        let program = Program::parse(".").unwrap();
+        let mut io = MemoryIo::new(&[]);
+        let debugger = debugger(&program, ".", &mut io);
+
+        assert_eq!(debugger.print("tape[0..3]"), Ok(()));
+        assert_eq!(debugger.print("tape[4..1000000]"), Ok(()));
+        assert_eq!(debugger.print("tape[7]"), Ok(()));
+        assert_eq!(debugger.print("tape[3..1]"), Err(String::from("The range 3..1 is backwards.")));
+        assert_eq!(debugger.print("tape[8]"), Err(String::from("Cell 8 is past the end of the tape, which has 8 cells.")));
+        assert_eq!(debugger.print(&format!("tape[{}]", usize::MAX)), Err(format!("Cell {} is past the end of the tape, which has 8 cells.", usize::MAX)));
+        assert_eq!(debugger.print("tape[a..2]"), Err(String::from("Invalid cell index a.")));
+        assert_eq!(debugger.print("cells"), Err(String::from("Can't print cells. Use tape[A..B], tape[A], dp or ip.")));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let program = Program::parse(".").unwrap();
+        let mut io = MemoryIo::new(&[]);
+        let mut debugger = debugger(&program, ".", &mut io);
+
+        assert_eq!(debugger.command(""), Ok(true));
+        assert_eq!(debugger.command("jump"), Err(String::from("Unknown command jump. Type help for a list of commands.")));
+        assert_eq!(debugger.command("step x"), Err(String::from("Invalid step count x.")));
+        assert_eq!(debugger.command("break"), Err(String::from("Use break LINE, break LINE:COL or break #INDEX.")));
+    }
+}
@@ -0,0 +1,272 @@
+//! Diagnostics about brainfuck source code, which can be rendered with snippets of the source.
+
+use std::{env, fmt, io, iter, str};
+use std::io::IsTerminal;
+
+use crate::ast::Span;
+
+/// How serious a diagnostic is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    /// The program can't be run.
+    Error,
+    /// The program can be run, but probably doesn't do what was intended.
+    Warning,
+}
+
+/// A message about a position in the source code.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A short description of the problem, e.g. "Unmatched [".
+    pub message: String,
+    /// Where the problem is.
+    pub span: Span,
+    /// Shown next to the problem in the snippet, e.g. "loop opened here, never closed".
+    pub label: String,
+    /// Other places in the source which explain the problem, with their own labels.
+    pub related: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn error(message: &str, span: Span, label: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.to_string(),
+            span,
+            label: label.to_string(),
+            related: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: &str, span: Span, label: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span, label)
+        }
+    }
+
+    /// Adds another labelled place in the source.
+    pub fn with_related(mut self, span: Span, label: &str) -> Diagnostic {
+        self.related.push((span, label.to_string()));
+        self
+    }
+
+    /// Formats the diagnostic with snippets of `source`, the code of the file `filename`, in the
+    /// style of a compiler error.
+    pub fn render(&self, source: &str, filename: &str, color: bool) -> String {
+        let paint = |code: &str, text: &str| paint(color, code, text);
+        let (name, main_color) = self.severity.name_and_color();
+
+        let gutter = self.gutter();
+        let mut out = format!("{}{}\n", paint(main_color, name), paint("1", &format!(": {}", self.message)));
+        out.push_str(&format!(
+            "{}{} {}:{}:{}\n",
+            " ".repeat(gutter),
+            paint("1;34", "-->"),
+            filename, self.span.line, self.span.col
+        ));
+        out.push_str(&self.snippet(source, color));
+
+        out
+    }
+
+    /// Formats just the lines of `source` the diagnostic points at, with its labels marked under
+    /// them, leaving out the message.
+    pub fn snippet(&self, source: &str, color: bool) -> String {
+        let paint = |code: &str, text: &str| paint(color, code, text);
+        let (_, main_color) = self.severity.name_and_color();
+
+        let mut labels: Vec<(Span, &str, bool)> = vec![(self.span, &self.label, true)];
+        labels.extend(self.related.iter().map(|(span, label)| (*span, label.as_str(), false)));
+        labels.sort_by_key(|(span, _, _)| span.start);
+
+        let gutter = self.gutter();
+        let bar = paint("1;34", &format!("{:gutter$} |", "", gutter = gutter));
+
+        let mut out = String::new();
+        let mut last_line = None;
+        for (span, label, primary) in labels {
+            let text = match source.lines().nth(span.line.wrapping_sub(1)) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // Line up the markers with the text, keeping tabs so they're expanded the same way
+            let line_start = text.as_ptr() as usize - source.as_ptr() as usize;
+            let before = &text[..span.start.saturating_sub(line_start).min(text.len())];
+            let padding: String = before.chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            let marked_end = span.end.saturating_sub(line_start).clamp(before.len(), text.len());
+            let width = text[before.len()..marked_end].chars().count().max(1);
+            let (marker, code) = if primary {
+                ("^", main_color)
+            } else {
+                ("-", "1;34")
+            };
+
+            // Labels on the same line share one copy of it
+            if last_line != Some(span.line) {
+                out.push_str(&format!("{}\n", bar));
+                out.push_str(&format!("{} {}\n", paint("1;34", &format!("{:>gutter$} |", span.line, gutter = gutter)), text));
+                last_line = Some(span.line);
+            }
+            out.push_str(&format!("{} {}{}\n", bar, padding, paint(code, &format!("{} {}", marker.repeat(width), label))));
+        }
+
+        out
+    }
+
+    /// How wide the line numbers in the snippet are.
+    fn gutter(&self) -> usize {
+        self.related.iter()
+            .map(|(span, _)| span.line)
+            .chain(iter::once(self.span.line))
+            .map(|line| line.to_string().len())
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+impl Severity {
+    fn name_and_color(self) -> (&'static str, &'static str) {
+        match self {
+            Severity::Error => ("error", "1;31"),
+            Severity::Warning => ("warning", "1;33"),
+        }
+    }
+}
+
+/// Wraps `text` in the ANSI escape `code` when `color` is set.
+fn paint(color: bool, code: &str, text: &str) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.span.line, self.span.col)
+    }
+}
+
+/// Whether diagnostics are printed in color.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorMode {
+    /// Only when printing to a terminal, and the `NO_COLOR` environment variable isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl str::FromStr for ColorMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<ColorMode, &'static str> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err("Unknown color mode"),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Whether diagnostics printed to stderr should be colored.
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "+[\n[-]]\n]";
+
+    fn span(start: usize, end: usize, line: usize, col: usize) -> Span {
+        Span { start, end, line, col }
+    }
+
+    fn unmatched() -> Diagnostic {
+        Diagnostic::error("Unmatched ]", span(8, 9, 3, 1), "no open loop to close")
+            .with_related(span(6, 7, 2, 4), "the last open loop was closed here")
+    }
+
+    #[test]
+    fn renders_a_snippet_with_related_places() {
+        let expected = "\
+error: Unmatched ]
+ --> test.b:3:1
+  |
+2 | [-]]
+  |    - the last open loop was closed here
+  |
+3 | ]
+  | ^ no open loop to close
+";
+        assert_eq!(unmatched().render(SOURCE, "test.b", false), expected);
+    }
+
+    #[test]
+    fn colors_only_when_asked() {
+        let colored = unmatched().render(SOURCE, "test.b", true);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"));
+
+        // Without the escape codes, it's the same as the plain rendering
+        let mut stripped = String::new();
+        let mut in_escape = false;
+        for c in colored.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => {},
+                _ => stripped.push(c),
+            }
+        }
+        assert_eq!(stripped, unmatched().render(SOURCE, "test.b", false));
+    }
+
+    #[test]
+    fn marks_the_whole_span_under_tabs() {
+        let source = "\tab, c!";
+        let diagnostic = Diagnostic::warning("Stray characters", span(1, 3, 1, 2), "here");
+        let rendered = diagnostic.render(source, "test.b", false);
+
+        assert!(rendered.starts_with("warning: Stray characters\n"));
+        assert!(rendered.ends_with("1 | \tab, c!\n  | \t^^ here\n"));
+    }
+
+    #[test]
+    fn renders_only_the_snippet() {
+        // Everything after the `-->` line of the full rendering
+        let rendered = unmatched().render(SOURCE, "test.b", false);
+        let (_, snippet) = rendered.split_once(" --> test.b:3:1\n").unwrap();
+        assert_eq!(unmatched().snippet(SOURCE, false), snippet);
+        assert!(snippet.starts_with("  |\n2 | [-]]\n"));
+    }
+
+    #[test]
+    fn describes_itself_in_one_line() {
+        assert_eq!(unmatched().to_string(), "Unmatched ] at line 3, column 1");
+    }
+
+    #[test]
+    fn parses_color_modes() {
+        assert_eq!("always".parse(), Ok(ColorMode::Always));
+        assert_eq!("never".parse(), Ok(ColorMode::Never));
+        assert_eq!("auto".parse(), Ok(ColorMode::Auto));
+        assert_eq!("sometimes".parse::<ColorMode>(), Err("Unknown color mode"));
+        assert!(ColorMode::Always.use_color());
+        assert!(!ColorMode::Never.use_color());
+    }
+}
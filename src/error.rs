@@ -0,0 +1,166 @@
+use std::{any, error, fmt, io};
+
+use crate::diagnostic::Diagnostic;
+
+/// Everything that can go wrong while loading or running a program.
+#[derive(Debug)]
+pub enum Error {
+    /// The options or settings were invalid, e.g. a tape size of 0.
+    Config(&'static str),
+    /// Cells of this many bits aren't supported.
+    UnsupportedCellSize(u8),
+    /// A compiled `.bfc` program couldn't be loaded.
+    InvalidIr(&'static str),
+    /// The program's source code has errors, such as unmatched brackets.
+    Syntax(Vec<Diagnostic>),
+    /// The token at instruction `ip` moved the data pointer left of the first cell.
+    TapeUnderflow { ip: usize, dp: usize },
+    /// The token at instruction `ip` moved the data pointer past the end of the tape.
+    TapeOverflow { ip: usize, dp: usize },
+    /// With automatic cell widths, the loop at instruction `ip` tested a cell which had gone up to
+    /// `value`, a multiple of 256. 8-bit cells would have wrapped it round to zero, so there's no
+    /// telling which width the program needs.
+    AmbiguousCellSize { ip: usize, value: u64 },
+    /// The program ran for this many steps without finishing.
+    StepLimit(u64),
+    /// The program ran for this many milliseconds without finishing.
+    Timeout(u64),
+    /// The program's input, or a tape init file, couldn't be decoded.
+    InvalidData(&'static str),
+    /// `rustfuck check` found this many problems in the code.
+    Lint(usize),
+    /// `.` tried to write more than the output limit allows.
+    OutputLimit,
+    /// Reading or writing a file or stream failed.
+    Io(&'static str, io::Error),
+    /// rustfuck panicked, which is a bug. Holds the panic's message.
+    Panic(String),
+}
+
+impl Error {
+    /// The process exit code for this kind of error.
+    ///
+    /// | Code | Meaning                                            |
+    /// |------|----------------------------------------------------|
+    /// | 1    | I/O error                                          |
+    /// | 2    | Invalid options                                    |
+    /// | 3    | Syntax error in the program, or an invalid `.bfc`  |
+    /// | 4    | Runtime fault, or a step or time limit reached     |
+    /// | 5    | Invalid input or tape init data                    |
+    /// | 6    | Output limit exceeded                              |
+    /// | 7    | `rustfuck check` found problems in the code        |
+    /// | 101  | rustfuck panicked, the same as any Rust program    |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(..) => 1,
+            Error::Config(_) | Error::UnsupportedCellSize(_) => 2,
+            Error::InvalidIr(_) | Error::Syntax(_) => 3,
+            Error::TapeUnderflow { .. } | Error::TapeOverflow { .. } => 4,
+            Error::AmbiguousCellSize { .. } => 4,
+            Error::StepLimit(_) | Error::Timeout(_) => 4,
+            Error::InvalidData(_) => 5,
+            Error::OutputLimit => 6,
+            Error::Lint(_) => 7,
+            Error::Panic(_) => 101,
+        }
+    }
+
+    /// Turns the payload of a panic caught with `catch_unwind` into an error.
+    pub fn from_panic(payload: Box<dyn any::Any + Send>) -> Error {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(x) => x.to_string(),
+            None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+        };
+        Error::Panic(message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(x) | Error::InvalidIr(x) | Error::InvalidData(x) => write!(f, "{}", x),
+            Error::UnsupportedCellSize(bits) => write!(f, "Unsupported cell size: {} bits", bits),
+            Error::Syntax(diagnostics) => {
+                let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", messages.join("; "))
+            },
+            Error::TapeUnderflow { ip, dp } => write!(
+                f, "Data pointer moved out of bounds (too far left) at instruction {}, data pointer {}", ip, dp
+            ),
+            Error::TapeOverflow { ip, dp } => write!(
+                f, "Data pointer moved out of bounds (too far right) at instruction {}, data pointer {}", ip, dp
+            ),
+            Error::AmbiguousCellSize { ip, value } => write!(
+                f, "Cell size is ambiguous: the loop at instruction {} tested a cell holding {}, which 8-bit cells would hold as 0", ip, value
+            ),
+            Error::StepLimit(steps) => write!(f, "Step limit of {} reached", steps),
+            Error::Timeout(ms) => write!(f, "Time limit of {}ms reached", ms),
+            Error::OutputLimit => write!(f, "Output limit exceeded"),
+            Error::Lint(count) => write!(f, "Found {} problems in the code", count),
+            Error::Io(x, err) => write!(f, "{}: {}", x, err),
+            Error::Panic(message) => write!(f, "Internal error: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::RunConfig;
+    use crate::testing::run;
+
+    #[test]
+    fn exit_codes_match_the_documented_table() {
+        assert_eq!(Error::Io("x", io::Error::other("y")).exit_code(), 1);
+        assert_eq!(Error::Config("x").exit_code(), 2);
+        assert_eq!(Error::UnsupportedCellSize(12).exit_code(), 2);
+        assert_eq!(Error::InvalidIr("x").exit_code(), 3);
+        assert_eq!(Error::Syntax(Vec::new()).exit_code(), 3);
+        assert_eq!(Error::TapeUnderflow { ip: 0, dp: 0 }.exit_code(), 4);
+        assert_eq!(Error::AmbiguousCellSize { ip: 0, value: 256 }.exit_code(), 4);
+        assert_eq!(Error::StepLimit(10).exit_code(), 4);
+        assert_eq!(Error::InvalidData("x").exit_code(), 5);
+        assert_eq!(Error::OutputLimit.exit_code(), 6);
+        assert_eq!(Error::Lint(2).exit_code(), 7);
+        assert_eq!(Error::Panic(String::new()).exit_code(), 101);
+    }
+
+    #[test]
+    fn panics_keep_their_message() {
+        let err = Error::from_panic(std::panic::catch_unwind(|| panic!("at {}", 3)).unwrap_err());
+        assert_eq!(err.to_string(), "Internal error: at 3");
+
+        let err = Error::from_panic(Box::new(5));
+        assert_eq!(err.to_string(), "Internal error: ");
+    }
+
+    #[test]
+    fn runtime_faults_report_where_they_happened() {
+        let config = RunConfig { tape_size: 4, ..RunConfig::default() };
+        let err = match run::<u8>("+>>>>", &config, &[]) {
+            Err(x) => x,
+            Ok(_) => panic!("expected the program to fail"),
+        };
+
+        assert!(matches!(err, Error::TapeOverflow { ip: 1, dp: 0 }));
+        assert_eq!(
+            err.to_string(),
+            "Data pointer moved out of bounds (too far right) at instruction 1, data pointer 0"
+        );
+    }
+
+    #[test]
+    fn io_errors_include_the_cause() {
+        let err = Error::Io("Could not open file", io::Error::other("no such file"));
+        assert_eq!(err.to_string(), "Could not open file: no such file");
+    }
+}
@@ -0,0 +1,402 @@
+use crate::ast::Ast;
+use crate::cell_size::CellSize;
+use crate::interpreter::{Interpreter, Status, Watch};
+use crate::error::Error;
+use crate::stream::{BfIo, Eof};
+use crate::tape::Tape;
+
+/// Settings which control how a program is executed.
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    /// The number of cells on the tape.
+    pub tape_size: usize,
+    /// What `,` stores in the current cell once the input has run out.
+    pub eof: Eof,
+    /// How many cells either side of the current one are shown by the debug character.
+    pub debug_window: usize,
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig {
+            tape_size: 30000,
+            eof: Eof::Zero,
+            debug_window: 8,
+        }
+    }
+}
+
+/// Why a program stopped running.
+pub enum Halt<T> {
+    /// The program ran to completion.
+    End(Tape<T>),
+    /// `.` at the given instruction went past the output limit.
+    OutputLimit(Tape<T>, usize),
+}
+
+/// The cell size chosen while running with automatic width detection, and why.
+pub enum AutoWidth {
+    /// A cell went above 255, so the program needed 64-bit cells.
+    Wide(Halt<u64>),
+    /// A cell went below zero, so the program relied on 8-bit wrapping.
+    Wrapped(Halt<u8>),
+    /// Every cell stayed within `0..=255`, so 8-bit and 64-bit cells behave the same.
+    InRange(Halt<u8>),
+}
+
+/// The reason `execute` returned.
+enum Stop<'a, T> {
+    /// The program stopped, with the final state of the cell width watch.
+    Halt(Halt<T>, Watch),
+    /// A cell went below zero while watching cell widths. The interpreter can carry on with
+    /// 8-bit cells.
+    Wrapped(Interpreter<'a, T>),
+}
+
+/// Handles a status from the interpreter: passes input and output through `io`, and dumps the
+/// tape to stderr for the debug character. Returns true once the program has halted.
+fn dispatch<T, I>(interpreter: &mut Interpreter<'_, T>, status: Status<T>, io: &mut I) -> Result<bool, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    match status {
+        Status::Running => {},
+        Status::NeedsInput => interpreter.provide_input(io.read_cell()?),
+        Status::Output(value, count) => io.write_cell(value, count)?,
+        Status::Debug => {
+            // Flush first, so the dump appears after the output which came before it
+            io.finish()?;
+            eprintln!("{}", interpreter.show_tape());
+        },
+        Status::Halted => {
+            io.finish()?;
+            return Ok(true);
+        },
+        Status::Error(x) => return Err(x),
+    }
+
+    Ok(false)
+}
+
+/// Drives the interpreter to completion, passing its input and output through `io`.
+fn execute<'a, T, I>(mut interpreter: Interpreter<'a, T>, io: &mut I) -> Result<Stop<'a, T>, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    loop {
+        let status = interpreter.run_until_event();
+
+        match dispatch(&mut interpreter, status, io) {
+            Ok(false) => {},
+            Ok(true) => {
+                let watch = interpreter.watch;
+                return Ok(Stop::Halt(Halt::End(interpreter.into_tape()), watch))
+            },
+            Err(Error::OutputLimit) => {
+                // Only `.` can go past the limit, and the instruction pointer has moved past it
+                let ip = interpreter.instruction_pointer() - 1;
+                let watch = interpreter.watch;
+                return Ok(Stop::Halt(Halt::OutputLimit(interpreter.into_tape(), ip), watch));
+            },
+            Err(x) => return Err(x),
+        }
+
+        if interpreter.watch == Watch::Wrapped {
+            return Ok(Stop::Wrapped(interpreter));
+        }
+    }
+}
+
+/// Why `drive` returned.
+#[derive(Debug, PartialEq)]
+pub enum Drive {
+    /// The program ran to completion.
+    Halted,
+    /// The hook asked to stop before the next token. Calling `drive` again carries on from there.
+    Paused,
+}
+
+/// Runs the interpreter a token at a time, calling `before` with it ahead of every token, e.g. to
+/// trace or count them. Input and output go through `io`, and the debug character dumps the tape
+/// to stderr, the same as `Program::run`.
+///
+/// `before` can return `Ok(false)` to pause before the token runs, or an error to stop the
+/// program with it. Once the program has halted or failed, driving it again gives the same result.
+pub fn drive<'a, T, I, F>(interpreter: &mut Interpreter<'a, T>, io: &mut I, mut before: F) -> Result<Drive, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+      F: FnMut(&Interpreter<'a, T>) -> Result<bool, Error>,
+{
+    loop {
+        if !before(interpreter)? {
+            return Ok(Drive::Paused);
+        }
+
+        let status = interpreter.step();
+        if dispatch(interpreter, status, io)? {
+            return Ok(Drive::Halted);
+        }
+    }
+}
+
+/// Runs up to `steps` tokens at full speed with `Interpreter::run_for`, passing input and output
+/// through `io` the same as `drive`. Returns why it stopped, along with how many tokens ran,
+/// counted the same way as `run_for` except that a `,` counts once it has its input.
+pub fn drive_for<T, I>(interpreter: &mut Interpreter<'_, T>, io: &mut I, steps: usize) -> Result<(Drive, usize), Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let mut ran = 0;
+    loop {
+        let (status, count) = interpreter.run_for(steps - ran);
+        ran += count;
+
+        // Only a plain `Running` means the steps are used up; after an event the next `run_for`
+        // still gets to run `End` with nothing left
+        let (needs_input, running) = (matches!(status, Status::NeedsInput), matches!(status, Status::Running));
+        if dispatch(interpreter, status, io)? {
+            return Ok((Drive::Halted, ran));
+        }
+        if needs_input {
+            ran += 1;
+        }
+        if running && ran >= steps {
+            return Ok((Drive::Paused, ran));
+        }
+    }
+}
+
+/// Runs the AST with cells of type `T`, starting from the given tape.
+pub fn run<T, I>(ast: &Ast, config: &RunConfig, tape: Tape<T>, io: &mut I) -> Result<Halt<T>, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let interpreter = Interpreter::new(ast, config, tape)?;
+
+    match execute(interpreter, io)? {
+        Stop::Halt(halt, _) => Ok(halt),
+        Stop::Wrapped(..) => unreachable!("Cell widths are only watched by run_auto"),
+    }
+}
+
+/// Runs the AST, detecting whether it needs 8-bit wrapping or wide cells.
+///
+/// The program starts out with 64-bit cells. If a cell goes below zero before any cell has gone
+/// above 255, the program relies on 8-bit wrapping: up to that point, 8-bit cells would have held
+/// exactly the same values, so the tape is converted and execution carries on with `u8` cells.
+/// Otherwise it keeps running with 64-bit cells.
+///
+/// Once a cell has gone above 255, a loop which tests a cell that has just gone up to a multiple
+/// of 256 fails with `Error::AmbiguousCellSize`. 8-bit cells would have wrapped that cell round to
+/// zero, so the program might rely on wrapping upwards, and with 64-bit cells it could loop forever.
+pub fn run_auto<I>(ast: &Ast, config: &RunConfig, tape: Tape<u64>, io: &mut I) -> Result<AutoWidth, Error>
+where I: BfIo<u64> + BfIo<u8>
+{
+    let mut interpreter = Interpreter::new(ast, config, tape)?;
+    interpreter.watch = if interpreter.cells().iter().any(|c| *c > 255) {
+        Watch::Wide
+    } else {
+        Watch::Watching
+    };
+
+    match execute::<u64, _>(interpreter, io)? {
+        Stop::Halt(halt, watch) => {
+            if watch == Watch::Wide {
+                return Ok(AutoWidth::Wide(halt));
+            }
+
+            // Every cell is within 0..=255, so converting to 8-bit cells loses nothing
+            let halt = match halt {
+                Halt::End(tape) => Halt::End(tape.convert()),
+                Halt::OutputLimit(tape, ip) => Halt::OutputLimit(tape.convert(), ip),
+            };
+            Ok(AutoWidth::InRange(halt))
+        },
+        Stop::Wrapped(interpreter) => {
+            match execute::<u8, _>(interpreter.convert(), io)? {
+                Stop::Halt(halt, _) => Ok(AutoWidth::Wrapped(halt)),
+                Stop::Wrapped(..) => unreachable!("Cell widths are no longer watched"),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{self, ParseConfig};
+    use crate::stream::{Flush, Input, IoMode, MemoryIo, Output, StreamIo};
+
+    fn ast(source: &str) -> Ast {
+        let mut ast = parse::parse(source, &ParseConfig::default()).unwrap();
+        parse::link_loops(&mut ast).unwrap();
+        ast
+    }
+
+    /// Runs `source` with automatic cell widths, returning the result and the bytes written.
+    fn auto(source: &str, tape: Tape<u64>) -> (AutoWidth, Vec<u8>) {
+        let ast = ast(source);
+
+        let mut out = Vec::new();
+        let width = {
+            let mut io = StreamIo::new(Input::new(&b""[..], None, false), Output::new(&mut out, Flush::Exit), IoMode::Bytes);
+            run_auto(&ast, &RunConfig::default(), tape, &mut io).unwrap()
+        };
+        (width, out)
+    }
+
+    #[test]
+    fn auto_keeps_programs_in_range_at_8_bits() {
+        match auto("+++[>++<-]>.", Tape::default()) {
+            (AutoWidth::InRange(Halt::End(tape)), out) => {
+                assert_eq!(out, [6]);
+                assert_eq!(tape.cells[1], 6u8);
+            },
+            _ => panic!("expected 8-bit cells"),
+        }
+    }
+
+    #[test]
+    fn auto_switches_to_8_bits_when_a_cell_wraps() {
+        match auto("+>-.<.", Tape::default()) {
+            (AutoWidth::Wrapped(Halt::End(tape)), out) => {
+                assert_eq!(out, [255, 1]);
+                assert_eq!(&tape.cells[..2], &[1u8, 255]);
+            },
+            _ => panic!("expected 8-bit wrapping"),
+        }
+    }
+
+    #[test]
+    fn auto_uses_64_bits_once_a_cell_goes_above_255() {
+        let source = format!("{}{}", "+".repeat(300), "-".repeat(301));
+        match auto(&source, Tape::default()) {
+            (AutoWidth::Wide(Halt::End(tape)), _) => assert_eq!(tape.cells[0], u64::MAX),
+            _ => panic!("expected 64-bit cells"),
+        }
+    }
+
+    #[test]
+    fn auto_stops_when_a_loop_relies_on_wrapping_upwards() {
+        let mut io = StreamIo::new(Input::new(&b""[..], None, false), Output::new(Vec::new(), Flush::Exit), IoMode::Bytes);
+        let res = run_auto(&ast("++[++]+++."), &RunConfig::default(), Tape::default(), &mut io);
+        assert!(matches!(res, Err(Error::AmbiguousCellSize { ip: 5, value: 256 })));
+
+        // Counting down through a multiple of 256 doesn't need 8-bit cells
+        let source = format!("{}[-]", "+".repeat(300));
+        match auto(&source, Tape::default()) {
+            (AutoWidth::Wide(Halt::End(tape)), _) => assert_eq!(tape.cells[0], 0),
+            _ => panic!("expected 64-bit cells"),
+        }
+    }
+
+    #[test]
+    fn auto_uses_64_bits_for_a_wide_initial_tape() {
+        match auto("-", Tape { cells: vec![1000], data_pointer: 0 }) {
+            (AutoWidth::Wide(Halt::End(tape)), _) => assert_eq!(tape.cells[0], 999),
+            _ => panic!("expected 64-bit cells"),
+        }
+    }
+
+    /// Records how it was used, and fails writes once `fail_after` values have been written.
+    struct Recorder {
+        io: MemoryIo<u16>,
+        finished: usize,
+        fail_after: usize,
+    }
+
+    impl BfIo<u16> for Recorder {
+        fn read_cell(&mut self) -> Result<Option<u16>, Error> {
+            self.io.read_cell()
+        }
+
+        fn write_cell(&mut self, value: u16, count: usize) -> Result<(), Error> {
+            if self.io.output.len() + count > self.fail_after {
+                return Err(Error::InvalidData("write failed"));
+            }
+            self.io.write_cell(value, count)
+        }
+
+        fn finish(&mut self) -> Result<(), Error> {
+            self.finished += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_against_any_io() {
+        let mut io = Recorder { io: MemoryIo::new(&[1000, 2000]), finished: 0, fail_after: usize::MAX };
+        let halt = run(&ast(",+.,-.,."), &RunConfig::default(), Tape::default(), &mut io).unwrap();
+
+        assert!(matches!(halt, Halt::End(_)));
+        assert_eq!(io.io.output, [1001, 1999, 0]);
+        assert_eq!(io.finished, 1);
+    }
+
+    #[test]
+    fn io_errors_stop_the_program() {
+        let mut io = Recorder { io: MemoryIo::new(&[]), finished: 0, fail_after: 1 };
+        let res = run(&ast("+.+.+."), &RunConfig::default(), Tape::default(), &mut io);
+
+        assert!(matches!(res, Err(Error::InvalidData("write failed"))));
+        assert_eq!(io.io.output, [1]);
+        assert_eq!(io.finished, 0);
+    }
+
+    #[test]
+    fn drives_with_a_hook_which_can_pause() {
+        let ast = ast("+.+.,.");
+        let mut interpreter = Interpreter::<u8>::new(&ast, &RunConfig::default(), Tape::default()).unwrap();
+        let mut io = MemoryIo::new(&[7]);
+
+        let mut seen = Vec::new();
+        let res = drive(&mut interpreter, &mut io, |interpreter| {
+            let ip = interpreter.instruction_pointer();
+            seen.push(ip);
+            Ok(ip != 3)
+        });
+        assert!(matches!(res, Ok(Drive::Paused)));
+        assert_eq!(seen, [0, 1, 2, 3]);
+        assert_eq!(io.output, [1]);
+
+        assert!(matches!(drive(&mut interpreter, &mut io, |_| Ok(true)), Ok(Drive::Halted)));
+        assert_eq!(io.output, [1, 2, 7]);
+
+        let res = drive(&mut interpreter, &mut io, |_| Err(Error::StepLimit(0)));
+        assert!(matches!(res, Err(Error::StepLimit(0))));
+    }
+
+    #[test]
+    fn drives_for_a_number_of_steps() {
+        let ast = ast("+.+.,.");
+        let mut interpreter = Interpreter::<u8>::new(&ast, &RunConfig::default(), Tape::default()).unwrap();
+        let mut io = MemoryIo::new(&[7]);
+
+        assert!(matches!(drive_for(&mut interpreter, &mut io, 3), Ok((Drive::Paused, 3))));
+        assert_eq!(io.output, [1]);
+        assert!(matches!(drive_for(&mut interpreter, &mut io, 3), Ok((Drive::Halted, 3))));
+        assert_eq!(io.output, [1, 2, 7]);
+    }
+
+    #[test]
+    fn stops_at_the_output_limit() {
+        let mut out = Vec::new();
+        let (halt, written) = {
+            let output = Output::new(&mut out, Flush::Exit).with_limit(Some(3));
+            let mut io = StreamIo::new(Input::new(&b""[..], None, false), output, IoMode::Bytes);
+            let halt = run::<u8, _>(&ast("+..>+++...+."), &RunConfig::default(), Tape::default(), &mut io).unwrap();
+            (halt, BfIo::<u8>::written(&io))
+        };
+
+        match halt {
+            Halt::OutputLimit(tape, ip) => {
+                // The second `.` of `...` is the one which went past the limit
+                assert_eq!(ip, 8);
+                assert_eq!(tape.data_pointer, 1);
+            },
+            Halt::End(_) => panic!("expected to stop at the output limit"),
+        }
+        assert_eq!(written, 3);
+        assert_eq!(out, [1, 1, 3]);
+    }
+}
@@ -0,0 +1,620 @@
+//! A C ABI for embedding rustfuck in programs written in other languages.
+//!
+//! The declarations are in `include/rustfuck.h`, which has to be kept in step with this module.
+//! The tests translate this module's signatures and `RunOptions` to C, and compare them with the
+//! header.
+//! Functions which can fail return 0 on success, or the error's exit code (see
+//! `Error::exit_code`), and the error's message can then be fetched with `rf_last_error`. Panics
+//! are caught before they can unwind into C, and give `ERR_PANIC`.
+
+use std::{io, panic, ptr, slice};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+use crate::{Program, OptConfig, RunConfig, AutoWidth, CellSize, Error};
+use crate::stream::{BfIo, Eof};
+
+/// Reads a value for `,` into `*value`. Returns 1 if a value was read, 0 at the end of the input,
+/// or -1 on error.
+pub type ReadFn = extern "C" fn(ctx: *mut c_void, value: *mut u64) -> c_int;
+
+/// Writes a value for `.`. Returns 0 on success, or anything else on error.
+pub type WriteFn = extern "C" fn(ctx: *mut c_void, value: u64) -> c_int;
+
+/// Settings for `rf_run`, mirroring `RunConfig` and `--cell-size`.
+#[repr(C)]
+pub struct RunOptions {
+    /// The number of cells on the tape.
+    pub tape_size: usize,
+    /// 0 to store zero at the end of input, 1 for -1, or 2 to leave the cell unchanged.
+    pub eof: c_int,
+    /// 8, 16, 32 or 64, or 0 for automatic width detection.
+    pub cell_bits: c_uint,
+}
+
+pub const OPT_COLLAPSE_DUPLICATED: c_uint = 1;
+pub const OPT_ZERO_CELL: c_uint = 2;
+pub const OPT_MOVE_VALUE: c_uint = 4;
+
+pub const EOF_ZERO: c_int = 0;
+pub const EOF_MINUS_ONE: c_int = 1;
+pub const EOF_UNCHANGED: c_int = 2;
+
+/// Returned when rustfuck panicked, the same as the exit code of a Rust program which panics.
+pub const ERR_PANIC: c_int = 101;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Records the message for `rf_last_error`.
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Records the error for `rf_last_error`, and returns its code.
+fn fail(error: Error) -> c_int {
+    set_last_error(error.to_string());
+    error.exit_code()
+}
+
+/// Runs the body of an `extern "C"` function, giving `on_panic` if it panics, since unwinding into
+/// C is undefined behaviour. The panic's message is recorded for `rf_last_error`.
+fn catch<R>(on_panic: R, body: impl FnOnce() -> R) -> R {
+    match panic::catch_unwind(panic::AssertUnwindSafe(body)) {
+        Ok(x) => x,
+        Err(payload) => {
+            fail(Error::from_panic(payload));
+            on_panic
+        },
+    }
+}
+
+/// `BfIo` over the callbacks given to `rf_run`.
+struct CallbackIo {
+    read: ReadFn,
+    write: WriteFn,
+    ctx: *mut c_void,
+}
+
+impl<T: CellSize> BfIo<T> for CallbackIo {
+    fn read_cell(&mut self) -> Result<Option<T>, Error> {
+        let mut value = 0;
+        match (self.read)(self.ctx, &mut value) {
+            0 => Ok(None),
+            1 => {
+                let cell = T::from_u64(value);
+                if cell.to_u64() != value {
+                    return Err(Error::InvalidData("Input value is too large for the cell size"));
+                }
+                Ok(Some(cell))
+            },
+            _ => Err(Error::Io("Could not read input", io::Error::other("read callback failed"))),
+        }
+    }
+
+    fn write_cell(&mut self, value: T, count: usize) -> Result<(), Error> {
+        for _ in 0..count {
+            if (self.write)(self.ctx, value.to_u64()) != 0 {
+                return Err(Error::Io("Could not write output", io::Error::other("write callback failed")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The message for the last error on this thread, or an empty string. The pointer is valid until
+/// the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn rf_last_error() -> *const c_char {
+    catch(b"\0".as_ptr() as *const c_char, || LAST_ERROR.with(|last| last.borrow().as_ptr()))
+}
+
+/// Parses `len` bytes of brainfuck source code, and stores the program in `*out`.
+///
+/// # Safety
+///
+/// `source` must point to `len` readable bytes, and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn rf_parse(source: *const c_char, len: usize, out: *mut *mut Program) -> c_int {
+    if source.is_null() || out.is_null() {
+        return fail(Error::Config("Null pointer passed to rf_parse"));
+    }
+
+    *out = ptr::null_mut();
+    catch(ERR_PANIC, || {
+        let bytes = slice::from_raw_parts(source as *const u8, len);
+        match Program::parse(&String::from_utf8_lossy(bytes)) {
+            Ok(program) => {
+                *out = Box::into_raw(Box::new(program));
+                0
+            },
+            Err(x) => fail(x),
+        }
+    })
+}
+
+/// Runs the optimizer passes chosen by `passes`, a combination of the `RF_OPT_*` flags.
+///
+/// # Safety
+///
+/// `program` must have come from `rf_parse` and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rf_optimize(program: *mut Program, passes: c_uint) {
+    if let Some(program) = program.as_mut() {
+        catch((), || program.optimize(&OptConfig {
+            collapse_duplicated: passes & OPT_COLLAPSE_DUPLICATED != 0,
+            zero_cell: passes & OPT_ZERO_CELL != 0,
+            move_value: passes & OPT_MOVE_VALUE != 0,
+        }));
+    }
+}
+
+/// The number of tokens in the program, including the final `End` token.
+///
+/// # Safety
+///
+/// `program` must have come from `rf_parse` and not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rf_program_len(program: *const Program) -> usize {
+    catch(0, || match program.as_ref() {
+        Some(program) => program.tokens().len(),
+        None => 0,
+    })
+}
+
+/// Stores the type (one of the `RF_TOKEN_*` values) and value of the token at `index`. Returns 0,
+/// or -1 if `index` is out of range.
+///
+/// # Safety
+///
+/// `program` must have come from `rf_parse` and not been freed, and `tk` and `value` must be
+/// valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn rf_program_token(program: *const Program, index: usize, tk: *mut c_int, value: *mut i32) -> c_int {
+    catch(-1, || {
+        let token = match program.as_ref().and_then(|p| p.tokens().get(index)) {
+            Some(x) => x,
+            None => return -1,
+        };
+        if tk.is_null() || value.is_null() {
+            return -1;
+        }
+
+        *tk = c_int::from(crate::ir::type_to_byte(token.tk));
+        *value = token.value;
+        0
+    })
+}
+
+/// Runs the program, calling `read` for `,` and `write` for `.` with `ctx` as their first argument.
+///
+/// # Safety
+///
+/// `program` must have come from `rf_parse` and not been freed, and `options` must be a valid
+/// pointer. The callbacks must be safe to call with `ctx`.
+#[no_mangle]
+pub unsafe extern "C" fn rf_run(program: *const Program, options: *const RunOptions, read: ReadFn, write: WriteFn, ctx: *mut c_void) -> c_int {
+    let (program, options) = match (program.as_ref(), options.as_ref()) {
+        (Some(p), Some(o)) => (p, o),
+        _ => return fail(Error::Config("Null pointer passed to rf_run")),
+    };
+
+    let eof = match options.eof {
+        EOF_ZERO => Eof::Zero,
+        EOF_MINUS_ONE => Eof::MinusOne,
+        EOF_UNCHANGED => Eof::Unchanged,
+        _ => return fail(Error::Config("Unknown EOF behaviour")),
+    };
+    let config = RunConfig {
+        tape_size: options.tape_size,
+        eof,
+        ..RunConfig::default()
+    };
+
+    let mut io = CallbackIo { read, write, ctx };
+    catch(ERR_PANIC, || {
+        let res = match options.cell_bits {
+            8 => program.run::<u8, _>(&config, &mut io).map(|_| ()),
+            16 => program.run::<u16, _>(&config, &mut io).map(|_| ()),
+            32 => program.run::<u32, _>(&config, &mut io).map(|_| ()),
+            64 => program.run::<u64, _>(&config, &mut io).map(|_| ()),
+            0 => program.run_auto(&config, Default::default(), &mut io).map(|_: AutoWidth| ()),
+            bits => Err(Error::UnsupportedCellSize(bits.min(255) as u8)),
+        };
+
+        match res {
+            Ok(_) => 0,
+            Err(x) => fail(x),
+        }
+    })
+}
+
+/// Frees a program returned by `rf_parse`. Does nothing if `program` is null.
+///
+/// # Safety
+///
+/// `program` must have come from `rf_parse` and not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rf_program_free(program: *mut Program) {
+    if !program.is_null() {
+        catch((), || drop(Box::from_raw(program)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::ffi::CStr;
+    use crate::TokenType;
+    use crate::ir::type_to_byte;
+
+    /// The `ctx` passed to the callbacks.
+    #[derive(Default)]
+    struct Buffers {
+        input: VecDeque<u64>,
+        output: Vec<u64>,
+    }
+
+    extern "C" fn read(ctx: *mut c_void, value: *mut u64) -> c_int {
+        let buffers = unsafe { &mut *(ctx as *mut Buffers) };
+        match buffers.input.pop_front() {
+            Some(x) => {
+                unsafe { *value = x };
+                1
+            },
+            None => 0,
+        }
+    }
+
+    extern "C" fn write(ctx: *mut c_void, value: u64) -> c_int {
+        let buffers = unsafe { &mut *(ctx as *mut Buffers) };
+        buffers.output.push(value);
+        0
+    }
+
+    extern "C" fn failing_write(_: *mut c_void, _: u64) -> c_int {
+        1
+    }
+
+    fn parse(source: &str) -> *mut Program {
+        let mut program = ptr::null_mut();
+        let code = unsafe { rf_parse(source.as_ptr() as *const c_char, source.len(), &mut program) };
+        assert_eq!(code, 0);
+        program
+    }
+
+    fn options(cell_bits: c_uint) -> RunOptions {
+        RunOptions { tape_size: 30000, eof: 1, cell_bits }
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(rf_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn runs_programs_through_callbacks() {
+        let program = parse(",[.-],.");
+        unsafe { rf_optimize(program, OPT_COLLAPSE_DUPLICATED | OPT_ZERO_CELL | OPT_MOVE_VALUE) };
+
+        let mut buffers = Buffers { input: vec![3].into(), ..Buffers::default() };
+        let ctx = &mut buffers as *mut Buffers as *mut c_void;
+        let code = unsafe { rf_run(program, &options(16), read, write, ctx) };
+        unsafe { rf_program_free(program) };
+
+        assert_eq!(code, 0);
+        assert_eq!(buffers.output, [3, 2, 1, 65535]);
+    }
+
+    #[test]
+    fn lists_tokens() {
+        let program = parse("++>");
+        unsafe { rf_optimize(program, OPT_COLLAPSE_DUPLICATED) };
+
+        let (mut tk, mut value) = (0, 0);
+        unsafe {
+            assert_eq!(rf_program_len(program), 3);
+            assert_eq!(rf_program_token(program, 0, &mut tk, &mut value), 0);
+            assert_eq!((tk, value), (2, 2));
+            assert_eq!(rf_program_token(program, 3, &mut tk, &mut value), -1);
+            rf_program_free(program);
+        }
+    }
+
+    #[test]
+    fn reports_errors_with_a_message() {
+        let mut program = ptr::null_mut();
+        let code = unsafe { rf_parse(b"[".as_ptr() as *const c_char, 1, &mut program) };
+        assert_eq!(code, 3);
+        assert!(program.is_null());
+        assert!(!last_error().is_empty());
+
+        let program = parse("+.");
+        let mut buffers = Buffers::default();
+        let ctx = &mut buffers as *mut Buffers as *mut c_void;
+        unsafe {
+            assert_eq!(rf_run(program, &options(12), read, write, ctx), 2);
+            assert_eq!(last_error(), "Unsupported cell size: 12 bits");
+
+            assert_eq!(rf_run(program, &options(8), read, failing_write, ctx), 1);
+            assert!(last_error().starts_with("Could not write output"));
+
+            assert_eq!(rf_run(program, ptr::null(), read, write, ctx), 2);
+            rf_program_free(program);
+        }
+    }
+
+    #[test]
+    fn catches_panics() {
+        assert_eq!(catch(ERR_PANIC, || panic!("boom")), ERR_PANIC);
+        assert_eq!(last_error(), "Internal error: boom");
+    }
+
+    /// The values of the header's `#define RF_<prefix>...` constants, by name without the prefix.
+    fn header_constants(prefix: &str) -> Vec<(String, i64)> {
+        include_str!("../include/rustfuck.h")
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.strip_prefix("#define RF_")?.split_whitespace();
+                let name = words.next()?.strip_prefix(prefix)?;
+                let value = words.next()?.trim_end_matches('u').parse().ok()?;
+                Some((name.to_string(), value))
+            })
+            .collect()
+    }
+
+    fn constants(pairs: &[(&str, i64)]) -> Vec<(String, i64)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+    }
+
+    #[test]
+    fn header_matches_the_token_types() {
+        // Adding a token type fails to compile here, as a reminder to add it to the header
+        let name = |tk| match tk {
+            TokenType::Invalid => None,
+            TokenType::End => Some("END"),
+            TokenType::Add => Some("ADD"),
+            TokenType::Sub => Some("SUB"),
+            TokenType::Left => Some("LEFT"),
+            TokenType::Right => Some("RIGHT"),
+            TokenType::Out => Some("OUT"),
+            TokenType::In => Some("IN"),
+            TokenType::LoopStart => Some("LOOP_START"),
+            TokenType::LoopEnd => Some("LOOP_END"),
+            TokenType::Set => Some("SET"),
+            TokenType::Move => Some("MOVE"),
+            TokenType::Debug => Some("DEBUG"),
+        };
+        let types = [
+            TokenType::Invalid, TokenType::End, TokenType::Add, TokenType::Sub, TokenType::Left,
+            TokenType::Right, TokenType::Out, TokenType::In, TokenType::LoopStart, TokenType::LoopEnd,
+            TokenType::Set, TokenType::Move, TokenType::Debug,
+        ];
+
+        let expected: Vec<(String, i64)> = types.iter()
+            .filter_map(|tk| Some((name(*tk)?.to_string(), i64::from(type_to_byte(*tk)))))
+            .collect();
+        assert_eq!(header_constants("TOKEN_"), expected);
+    }
+
+    #[test]
+    fn header_matches_the_exit_codes() {
+        // Adding an error fails to compile here, as a reminder to check its code is in the header
+        let name = |error: &Error| match error {
+            Error::Io(..) => "IO",
+            Error::Config(_) | Error::UnsupportedCellSize(_) => "CONFIG",
+            Error::InvalidIr(_) | Error::Syntax(_) => "SYNTAX",
+            Error::TapeUnderflow { .. } | Error::TapeOverflow { .. } => "RUNTIME",
+            Error::AmbiguousCellSize { .. } | Error::StepLimit(_) | Error::Timeout(_) => "RUNTIME",
+            Error::InvalidData(_) => "INVALID_DATA",
+            Error::OutputLimit => "OUTPUT_LIMIT",
+            Error::Lint(_) => "LINT",
+            Error::Panic(_) => "PANIC",
+        };
+        let errors = [
+            Error::Io("x", io::Error::other("y")),
+            Error::Config("x"),
+            Error::UnsupportedCellSize(12),
+            Error::InvalidIr("x"),
+            Error::Syntax(Vec::new()),
+            Error::TapeUnderflow { ip: 0, dp: 0 },
+            Error::TapeOverflow { ip: 0, dp: 0 },
+            Error::AmbiguousCellSize { ip: 0, value: 256 },
+            Error::StepLimit(1),
+            Error::Timeout(1),
+            Error::InvalidData("x"),
+            Error::OutputLimit,
+            Error::Lint(1),
+            Error::Panic(String::new()),
+        ];
+
+        let header = header_constants("ERR_");
+        for error in &errors {
+            let code = header.iter().find(|(n, _)| n == name(error)).map(|(_, code)| *code);
+            assert_eq!(code, Some(i64::from(error.exit_code())), "RF_ERR_{}", name(error));
+        }
+
+        let mut names: Vec<&str> = errors.iter().map(name).collect();
+        names.dedup();
+        let header_names: Vec<&str> = header.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(header_names, names);
+        assert!(header.contains(&("PANIC".to_string(), i64::from(ERR_PANIC))));
+    }
+
+    #[test]
+    fn header_matches_the_options() {
+        let all = OPT_COLLAPSE_DUPLICATED | OPT_ZERO_CELL | OPT_MOVE_VALUE;
+        assert_eq!(header_constants("OPT_"), constants(&[
+            ("COLLAPSE_DUPLICATED", i64::from(OPT_COLLAPSE_DUPLICATED)),
+            ("ZERO_CELL", i64::from(OPT_ZERO_CELL)),
+            ("MOVE_VALUE", i64::from(OPT_MOVE_VALUE)),
+            ("ALL", i64::from(all)),
+        ]));
+        assert_eq!(header_constants("EOF_"), constants(&[
+            ("ZERO", i64::from(EOF_ZERO)),
+            ("MINUS_ONE", i64::from(EOF_MINUS_ONE)),
+            ("UNCHANGED", i64::from(EOF_UNCHANGED)),
+        ]));
+    }
+
+    /// Splits C declarations or Rust types into identifiers and `*`s, so that spacing doesn't
+    /// matter when comparing them.
+    fn c_words(text: &str) -> Vec<String> {
+        text.replace('*', " * ").split_whitespace().map(String::from).collect()
+    }
+
+    /// The C type a Rust type in this module's signatures is declared as in the header.
+    fn c_type(rust: &str) -> String {
+        let rust = rust.trim();
+        if let Some(inner) = rust.strip_prefix("*const ") {
+            return format!("const {} *", c_type(inner));
+        }
+        if let Some(inner) = rust.strip_prefix("*mut ") {
+            return format!("{} *", c_type(inner));
+        }
+
+        let c = match rust {
+            "c_char" => "char",
+            "c_int" => "int",
+            "c_uint" => "unsigned",
+            "c_void" => "void",
+            "usize" => "size_t",
+            "i32" => "int32_t",
+            "u64" => "uint64_t",
+            "Program" => "rf_program",
+            "RunOptions" => "rf_run_options",
+            "ReadFn" => "rf_read_fn",
+            "WriteFn" => "rf_write_fn",
+            _ => panic!("no C type for {}", rust),
+        };
+        c.to_string()
+    }
+
+    /// Writes a function or function pointer declaration the same way for both languages.
+    fn signature(ret: &str, name: &str, params: &[String]) -> String {
+        let params: Vec<String> = params.iter().map(|p| c_words(p).join(" ")).collect();
+        format!("{} {}({})", c_words(ret).join(" "), name, params.join(", "))
+    }
+
+    /// Every `extern "C"` function and function pointer type in this module, translated to C.
+    fn rust_signatures() -> Vec<String> {
+        let source = include_str!("ffi.rs");
+        let source = &source[..source.find("#[cfg(test)]").unwrap()];
+
+        let mut signatures: Vec<String> = source.lines()
+            .filter_map(|line| {
+                let line = line.strip_prefix("pub ")?;
+                let line = line.strip_prefix("unsafe ").unwrap_or(line);
+                let (name, rest) = match line.strip_prefix("extern \"C\" fn ") {
+                    Some(rest) => rest.split_once('(')?,
+                    None => {
+                        let (name, rest) = line.strip_prefix("type ")?.split_once(" = extern \"C\" fn(")?;
+                        (name, rest)
+                    },
+                };
+                let (params, ret) = rest.split_once(')')?;
+                let ret = ret.trim_start().strip_prefix("-> ")
+                    .map_or("void".to_string(), |ret| c_type(ret.trim_end_matches([' ', '{', ';'])));
+                let params: Vec<String> = params.split(", ")
+                    .filter(|p| !p.is_empty())
+                    .map(|p| c_type(p.split_once(':').unwrap().1))
+                    .collect();
+
+                let name = if line.starts_with("type ") { format!("(*{})", c_type(name)) } else { name.to_string() };
+                Some(signature(&ret, &name, &params))
+            })
+            .collect();
+        signatures.sort();
+        signatures
+    }
+
+    /// Every function prototype and function pointer typedef in the header.
+    fn header_signatures() -> Vec<String> {
+        // Drop the comments, the preprocessor lines and the C++ guard, leaving the declarations
+        let mut parts = include_str!("../include/rustfuck.h").split("/*");
+        let mut code = parts.next().unwrap().to_string();
+        for part in parts {
+            code.push_str(part.split_once("*/").unwrap().1);
+        }
+        let code: Vec<&str> = code.lines()
+            .filter(|line| !line.starts_with('#') && !line.contains("extern \"C\"") && line.trim() != "}")
+            .collect();
+
+        let mut signatures: Vec<String> = code.join(" ")
+            .split(';')
+            .filter_map(|decl| {
+                let decl = decl.trim();
+                let (head, params) = match decl.strip_prefix("typedef ") {
+                    // A function pointer, `typedef ret (*name)(params)`
+                    Some(decl) => {
+                        let (ret, rest) = decl.split_once("(*")?;
+                        let (name, params) = rest.split_once(")(")?;
+                        (format!("{} (*{})", ret, name), params)
+                    },
+                    None => {
+                        let (head, params) = decl.split_once('(')?;
+                        let mut head = c_words(head);
+                        let name = head.pop()?;
+                        (format!("{} {}", head.join(" "), name), params)
+                    },
+                };
+                let (ret, name) = head.rsplit_once(' ')?;
+
+                // Every parameter is named, and the name is its last word
+                let params: Vec<String> = params.strip_suffix(')')?
+                    .split(',')
+                    .map(c_words)
+                    .filter(|p| p != &["void"])
+                    .map(|mut p| {
+                        p.pop();
+                        p.join(" ")
+                    })
+                    .collect();
+                Some(signature(ret, name, &params))
+            })
+            .collect();
+        signatures.sort();
+        signatures
+    }
+
+    #[test]
+    fn header_matches_the_functions() {
+        let rust = rust_signatures();
+        assert!(rust.contains(&"int rf_run(const rf_program *, const rf_run_options *, rf_read_fn, rf_write_fn, void *)".to_string()));
+        assert!(rust.contains(&"int (*rf_read_fn)(void *, uint64_t *)".to_string()));
+        assert_eq!(header_signatures(), rust);
+    }
+
+    #[test]
+    fn header_matches_the_run_options() {
+        // Listing the fields fails to compile if one is added or renamed
+        let RunOptions { tape_size: _, eof: _, cell_bits: _ } = RunOptions { tape_size: 0, eof: 0, cell_bits: 0 };
+
+        let source = include_str!("ffi.rs");
+        let body = source.split_once("pub struct RunOptions {").unwrap().1.split_once('}').unwrap().0;
+        let rust: Vec<String> = body.lines()
+            .filter_map(|line| line.trim().strip_prefix("pub "))
+            .map(|field| {
+                let (name, ty) = field.trim_end_matches(',').split_once(": ").unwrap();
+                format!("{} {}", c_words(&c_type(ty)).join(" "), name)
+            })
+            .collect();
+
+        let header = include_str!("../include/rustfuck.h");
+        let body = header.split_once("typedef struct rf_run_options {").unwrap().1.split_once("} rf_run_options;").unwrap().0;
+        let c: Vec<String> = body.split(';')
+            .filter_map(|field| {
+                // Each field is followed by a comment
+                let field = field.split("*/").last()?.trim();
+                (!field.is_empty()).then(|| c_words(field).join(" "))
+            })
+            .collect();
+
+        assert_eq!(c, rust);
+        assert_eq!(rust, ["size_t tape_size", "int eof", "unsigned cell_bits"]);
+    }
+}
@@ -0,0 +1,619 @@
+use std::convert::TryFrom;
+
+use crate::ast::{Ast, Token, TokenType};
+use crate::cell_size::CellSize;
+use crate::error::Error;
+use crate::execute::RunConfig;
+use crate::stream::Eof;
+use crate::tape::Tape;
+
+/// Tracks whether a program needs wide cells while running with automatic width detection.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Watch {
+    /// Cell values aren't being checked.
+    Off,
+    /// Every cell has stayed within `0..=255` so far.
+    Watching,
+    /// A cell has gone above 255, so the program needs wide cells, unless a loop relies on a
+    /// cell wrapping back round to zero.
+    Wide,
+    /// A cell went below zero, so the program relies on 8-bit wrapping.
+    Wrapped,
+}
+
+impl Watch {
+    /// Checks a cell value which has just changed.
+    ///
+    /// Only meaningful for 64-bit cells: while watching, every cell is within `0..=255`, so a
+    /// single change can't take a cell above `u64::MAX / 2` without wrapping.
+    fn check(&mut self, value: u64) {
+        if *self != Watch::Watching || value <= 255 {
+            return;
+        }
+
+        if value > u64::MAX / 2 {
+            *self = Watch::Wrapped;
+        } else {
+            *self = Watch::Wide;
+        }
+    }
+}
+
+/// What happened when the interpreter was stepped.
+#[derive(Debug)]
+pub enum Status<T> {
+    /// The program can carry on running.
+    Running,
+    /// `,` is waiting for a value from `Interpreter::provide_input`.
+    NeedsInput,
+    /// `.` wrote the value `count` times. More than one `.` is run at once when the optimizer
+    /// has collapsed them into a single token.
+    Output(T, usize),
+    /// The debug character asked for the tape to be shown, e.g. with `Interpreter::show_tape`.
+    Debug,
+    /// The program has finished.
+    Halted,
+    /// The program failed, and can't carry on.
+    Error(Error),
+}
+
+/// A brainfuck interpreter which runs a program a step at a time.
+///
+/// The interpreter doesn't do any I/O itself: `.` and `,` are reported through `Status`, so the
+/// caller decides where output goes and when input is provided.
+pub struct Interpreter<'a, T> {
+    ast: &'a Ast,
+    config: RunConfig,
+    cells: Vec<T>,
+    data_pointer: usize,
+    instruction_pointer: usize,
+    pub(crate) watch: Watch,
+    /// The cell which has just gone up to a nonzero multiple of 256 while wide, if any.
+    rising: Option<usize>,
+}
+
+impl<'a, T> Interpreter<'a, T>
+where T: CellSize + Clone + Copy
+{
+    /// Creates an interpreter at the start of the AST, using the given tape.
+    ///
+    /// The tape must fit within the tape size, and its data pointer must point at one of its cells.
+    pub(crate) fn new(ast: &'a Ast, config: &RunConfig, tape: Tape<T>) -> Result<Interpreter<'a, T>, Error> {
+        if config.tape_size < 1 {
+            return Err(Error::Config("Tape size must be greater than 0"));
+        }
+
+        let Tape { mut cells, data_pointer } = tape;
+        if cells.len() > config.tape_size {
+            return Err(Error::Config("Initial tape is longer than the tape size"));
+        }
+
+        // The tape never holds cells past the tape size, so `reach` only has to check its length
+        let padded = config.tape_size.min(1000);
+        if cells.len() < padded {
+            cells.extend(
+                T::get_zeroes(padded - cells.len())
+            );
+        }
+
+        if data_pointer >= config.tape_size || data_pointer >= cells.len() {
+            return Err(Error::Config("Initial data pointer is outside the tape"));
+        }
+
+        Ok(Interpreter {
+            ast,
+            config: config.clone(),
+            cells,
+            data_pointer,
+            instruction_pointer: 0,
+            watch: Watch::Off,
+            rising: None,
+        })
+    }
+
+    /// Moves the interpreter to cells of another size, truncating values which don't fit.
+    pub(crate) fn convert<U>(self) -> Interpreter<'a, U>
+    where U: CellSize + Clone + Copy
+    {
+        let Interpreter { ast, config, cells, data_pointer, instruction_pointer, .. } = self;
+        let tape = Tape { cells, data_pointer }.convert::<U>();
+        Interpreter {
+            ast,
+            config,
+            cells: tape.cells,
+            data_pointer: tape.data_pointer,
+            instruction_pointer,
+            watch: Watch::Off,
+            rising: None,
+        }
+    }
+
+    /// The index of the next token to run.
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    /// The index of the current cell.
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// The cells allocated so far. Cells past the end are all zero.
+    pub fn cells(&self) -> &[T] {
+        &self.cells
+    }
+
+    /// Formats the data pointer and the cells within `RunConfig::debug_window` of it, with the
+    /// current cell in brackets, e.g. `dp 2, tape[0..5] = 0 72 [101] 0 0`.
+    pub fn show_tape(&self) -> String {
+        let dp = self.data_pointer;
+        let start = dp.saturating_sub(self.config.debug_window);
+        let end = (dp + self.config.debug_window + 1).min(self.config.tape_size);
+
+        let values: Vec<String> = (start..end)
+            .map(|i| {
+                let value = self.cells.get(i).map_or(0, |c| c.to_u64());
+                if i == dp { format!("[{}]", value) } else { value.to_string() }
+            })
+            .collect();
+
+        format!("dp {}, tape[{}..{}] = {}", dp, start, end, values.join(" "))
+    }
+
+    /// Takes the tape out of the interpreter.
+    pub fn into_tape(self) -> Tape<T> {
+        Tape {
+            cells: self.cells,
+            data_pointer: self.data_pointer,
+        }
+    }
+
+    /// Changes the value of the cell at `pos`, or fails if it's past the end of the tape.
+    pub fn set_cell(&mut self, pos: usize, value: T) -> Result<(), Error> {
+        self.reach(pos)?;
+        self.cells[pos] = value;
+        self.watch.check(value.to_u64());
+        if self.rising == Some(pos) {
+            self.rising = None;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure the cell at `pos` exists, or fails if it's past the end of the tape.
+    fn reach(&mut self, pos: usize) -> Result<(), Error> {
+        if pos < self.cells.len() || grow(&mut self.cells, self.config.tape_size, pos) {
+            return Ok(());
+        }
+
+        Err(Error::TapeOverflow { ip: self.instruction_pointer, dp: self.data_pointer })
+    }
+
+    /// Runs a single token.
+    ///
+    /// Once the program has halted or failed, stepping again gives the same status, since the
+    /// instruction pointer doesn't move past `End` or the token which failed.
+    #[inline]
+    pub fn step(&mut self) -> Status<T> {
+        match self.watch {
+            Watch::Off => self.run::<false, false, false>(0).0,
+            _ => self.run::<true, false, false>(0).0,
+        }
+    }
+
+    /// Runs tokens until one gives a status other than `Running`, or a cell wraps while watching.
+    ///
+    /// This is the hot loop used by `execute`, so it avoids going back to the caller after every
+    /// token. Like `step`, cell widths are only checked while they're being watched, which keeps
+    /// the checks out of runs with a fixed cell size.
+    pub(crate) fn run_until_event(&mut self) -> Status<T> {
+        match self.watch {
+            Watch::Off => self.run::<false, true, false>(0).0,
+            _ => self.run::<true, true, false>(0).0,
+        }
+    }
+
+    /// Runs up to `steps` tokens at full speed, stopping early at anything other than
+    /// `Status::Running`, or when a cell wraps while watching cell widths.
+    ///
+    /// Returns the status along with how many tokens ran. `End`, a `,` still waiting for its
+    /// input and a token which failed aren't counted, and `End` runs even once the steps are used
+    /// up, so a program of exactly `steps` tokens still halts.
+    pub fn run_for(&mut self, steps: usize) -> (Status<T>, usize) {
+        match self.watch {
+            Watch::Off => self.run::<false, true, true>(steps),
+            _ => self.run::<true, true, true>(steps),
+        }
+    }
+
+    /// Runs one token, or carries on until an event when `LOOP` is set. When `LIMIT` is set, at
+    /// most `budget` tokens other than `End` are run, and the status comes back with how many were
+    /// counted, as described for `run_for`.
+    ///
+    /// The registers and the tape are moved into locals while running, since a write to a cell
+    /// would otherwise make the compiler reload every field of the interpreter.
+    #[inline(always)]
+    fn run<const WATCH: bool, const LOOP: bool, const LIMIT: bool>(&mut self, budget: usize) -> (Status<T>, usize) {
+        let mut regs = Registers {
+            ip: self.instruction_pointer,
+            dp: self.data_pointer,
+            watch: self.watch,
+            rising: self.rising,
+        };
+        let mut cells = std::mem::take(&mut self.cells);
+        let ast = self.ast.as_slice();
+        let tape_size = self.config.tape_size;
+
+        let mut left = budget;
+        let status = loop {
+            if LIMIT {
+                if left == 0 && ast[regs.ip].tk != TokenType::End {
+                    break Status::Running;
+                }
+                left = left.wrapping_sub(1);
+            }
+
+            match regs.run_token::<T, WATCH>(ast, tape_size, &mut cells) {
+                Ok(Status::Running) if LOOP && (!WATCH || regs.watch != Watch::Wrapped) => {},
+                Ok(status @ (Status::NeedsInput | Status::Halted)) if LIMIT => {
+                    left = left.wrapping_add(1);
+                    break status;
+                },
+                Ok(status) => break status,
+                Err(x) if LIMIT => {
+                    left = left.wrapping_add(1);
+                    break Status::Error(x);
+                },
+                Err(x) => break Status::Error(x),
+            }
+        };
+
+        self.instruction_pointer = regs.ip;
+        self.data_pointer = regs.dp;
+        self.watch = regs.watch;
+        self.rising = regs.rising;
+        self.cells = cells;
+        (status, budget - left)
+    }
+
+    /// Gives `,` its value, or `None` at the end of the input, and moves past it.
+    ///
+    /// Has no effect unless the interpreter is waiting for input.
+    pub fn provide_input(&mut self, value: Option<T>) {
+        if self.ast[self.instruction_pointer].tk != TokenType::In {
+            return;
+        }
+
+        let cell = &mut self.cells[self.data_pointer];
+        match value {
+            Some(value) => *cell = value,
+            None => match self.config.eof {
+                Eof::Zero => *cell = T::from_tk_value(0),
+                Eof::MinusOne => *cell = T::from_tk_value(-1),
+                Eof::Unchanged => {},
+            },
+        }
+
+        self.watch.check(cell.to_u64());
+        if self.rising == Some(self.data_pointer) {
+            self.rising = None;
+        }
+        self.instruction_pointer += 1;
+    }
+
+    /// Runs until the program needs input, halts or fails, collecting its output on the way.
+    pub fn run_until_input(&mut self, output: &mut Vec<T>) -> Status<T> {
+        loop {
+            match self.step() {
+                Status::Running => {},
+                Status::Output(value, count) => {
+                    output.extend(std::iter::repeat_n(value, count));
+                },
+                status => return status,
+            }
+        }
+    }
+}
+
+/// The parts of the interpreter which change on every token, kept apart from the tape while
+/// running.
+struct Registers {
+    ip: usize,
+    dp: usize,
+    watch: Watch,
+    rising: Option<usize>,
+}
+
+impl Registers {
+    /// Checks a cell value which has just changed, remembering whether it went up to a nonzero
+    /// multiple of 256 after the program needed wide cells. 8-bit cells would have wrapped it round
+    /// to zero.
+    #[inline(always)]
+    fn check(&mut self, pos: usize, value: u64, up: bool) {
+        self.watch.check(value);
+
+        if up && self.watch == Watch::Wide && value != 0 && value.is_multiple_of(256) {
+            self.rising = Some(pos);
+        } else if self.rising == Some(pos) {
+            self.rising = None;
+        }
+    }
+
+    /// Makes sure the cell at `pos` exists, or fails if it's past the end of the tape.
+    #[inline(always)]
+    fn reach<T: CellSize + Clone>(&self, cells: &mut Vec<T>, tape_size: usize, pos: usize) -> Result<(), Error> {
+        if pos < cells.len() || grow(cells, tape_size, pos) {
+            return Ok(());
+        }
+
+        Err(Error::TapeOverflow { ip: self.ip, dp: self.dp })
+    }
+
+    #[inline(always)]
+    fn run_token<T, const WATCH: bool>(&mut self, ast: &[Token], tape_size: usize, cells: &mut Vec<T>) -> Result<Status<T>, Error>
+    where T: CellSize + Clone + Copy
+    {
+        let token = &ast[self.ip];
+        let data_pointer = self.dp;
+
+        match token.tk {
+            TokenType::Add => {
+                cells[data_pointer].add_to_cell(T::from_tk_value(token.value));
+                if WATCH {
+                    self.check(data_pointer, cells[data_pointer].to_u64(), true);
+                }
+            },
+            TokenType::Sub => {
+                cells[data_pointer].sub_from_cell(T::from_tk_value(token.value));
+                if WATCH {
+                    self.check(data_pointer, cells[data_pointer].to_u64(), false);
+                }
+            },
+            TokenType::Left => {
+                if data_pointer < token.value as usize {
+                    return Err(Error::TapeUnderflow { ip: self.ip, dp: data_pointer })
+                }
+                self.dp -= token.value as usize;
+            },
+            TokenType::Right => {
+                let new_pos = data_pointer + token.value as usize;
+                self.reach(cells, tape_size, new_pos)?;
+                self.dp = new_pos;
+            },
+            TokenType::LoopStart | TokenType::LoopEnd if WATCH && self.rising == Some(data_pointer) => {
+                // The loop only stops here with 8-bit cells, so there's no telling which width
+                // the program needs
+                return Err(Error::AmbiguousCellSize { ip: self.ip, value: cells[data_pointer].to_u64() });
+            },
+            TokenType::LoopStart if cells[data_pointer].is_zero() => {
+                self.ip = token.value as usize;
+            },
+            TokenType::LoopEnd if cells[data_pointer].is_nonzero() => {
+                self.ip = token.value as usize;
+            },
+            TokenType::In => {
+                return Ok(Status::NeedsInput);
+            },
+            TokenType::Out => {
+                self.ip += 1;
+                return Ok(Status::Output(cells[data_pointer], token.value as usize));
+            },
+            TokenType::Set => {
+                cells[data_pointer] = T::from_tk_value(token.value);
+                if WATCH && self.rising == Some(data_pointer) {
+                    self.rising = None;
+                }
+            },
+            TokenType::Move if cells[data_pointer].is_nonzero() => {
+                // Offsets loaded from IR can be anywhere in the range of an i32
+                let dest = data_pointer as i64 + token.value as i64;
+                if dest < 0 {
+                    return Err(Error::TapeUnderflow { ip: self.ip, dp: data_pointer })
+                }
+
+                let dest = match usize::try_from(dest) {
+                    Ok(x) => x,
+                    Err(_) => return Err(Error::TapeOverflow { ip: self.ip, dp: data_pointer }),
+                };
+                self.reach(cells, tape_size, dest)?;
+
+                let val = cells[data_pointer];
+                cells[dest].add_to_cell(val);
+                if WATCH {
+                    self.check(dest, cells[dest].to_u64(), true);
+                }
+            },
+            TokenType::Debug => {
+                self.ip += 1;
+                return Ok(Status::Debug);
+            },
+            TokenType::End => {
+                return Ok(Status::Halted);
+            },
+            _ => {},
+        }
+
+        self.ip += 1;
+        Ok(Status::Running)
+    }
+}
+
+/// The slow path of `reach`, kept out of the run loop. Returns `false` if `pos` is past
+/// the end of the tape.
+#[cold]
+#[inline(never)]
+fn grow<T: CellSize + Clone>(cells: &mut Vec<T>, tape_size: usize, pos: usize) -> bool {
+    if pos >= tape_size {
+        return false;
+    }
+
+    // Allocate more space for the tape, we need it
+    let len = pos.saturating_add(1000).min(tape_size);
+    cells.extend(
+        T::get_zeroes(len - cells.len())
+    );
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run;
+    use crate::{ParseConfig, Program};
+
+    fn with_eof(eof: Eof) -> RunConfig {
+        RunConfig { eof, ..RunConfig::default() }
+    }
+
+    #[test]
+    fn eof_zero_clears_the_cell() {
+        let (out, _) = run::<u8>("+++,.", &with_eof(Eof::Zero), &[]).unwrap();
+        assert_eq!(out, [0]);
+    }
+
+    #[test]
+    fn eof_minus_one_sets_every_bit() {
+        let (out, _) = run::<u8>("+++,.", &with_eof(Eof::MinusOne), &[]).unwrap();
+        assert_eq!(out, [255]);
+
+        let (out, _) = run::<u16>("+++,.", &with_eof(Eof::MinusOne), &[]).unwrap();
+        assert_eq!(out, [65535]);
+    }
+
+    #[test]
+    fn eof_unchanged_leaves_the_cell() {
+        let (out, _) = run::<u8>("+++,.", &with_eof(Eof::Unchanged), &[]).unwrap();
+        assert_eq!(out, [3]);
+    }
+
+    #[test]
+    fn input_is_read_before_eof() {
+        let (out, _) = run::<u8>(",.,.,.", &with_eof(Eof::MinusOne), &[7, 8]).unwrap();
+        assert_eq!(out, [7, 8, 255]);
+    }
+
+    #[test]
+    fn steps_through_input_and_output() {
+        let program = Program::parse(",+.").unwrap();
+        let mut interpreter = program.interpreter::<u8>(&RunConfig::default(), Tape::default()).unwrap();
+
+        assert!(matches!(interpreter.step(), Status::NeedsInput));
+        // Still waiting until input is provided
+        assert!(matches!(interpreter.step(), Status::NeedsInput));
+        interpreter.provide_input(Some(4));
+        assert_eq!(interpreter.instruction_pointer(), 1);
+
+        assert!(matches!(interpreter.step(), Status::Running));
+        assert!(matches!(interpreter.step(), Status::Output(5, 1)));
+        assert!(matches!(interpreter.step(), Status::Halted));
+        assert!(matches!(interpreter.step(), Status::Halted));
+        assert_eq!(interpreter.cells()[0], 5);
+    }
+
+    #[test]
+    fn steps_with_the_same_width_checks_as_a_full_run() {
+        let program = Program::parse("++[++]").unwrap();
+        let mut interpreter = program.interpreter::<u64>(&RunConfig::default(), Tape::default()).unwrap();
+        interpreter.watch = Watch::Watching;
+
+        let status = loop {
+            match interpreter.step() {
+                Status::Running => {},
+                status => break status,
+            }
+        };
+        assert!(matches!(status, Status::Error(Error::AmbiguousCellSize { ip: 5, value: 256 })));
+
+        // Without watching, the same program just keeps counting up
+        let mut interpreter = program.interpreter::<u64>(&RunConfig::default(), Tape::default()).unwrap();
+        for _ in 0..1000 {
+            assert!(matches!(interpreter.step(), Status::Running));
+        }
+        assert_eq!(interpreter.watch, Watch::Off);
+    }
+
+    #[test]
+    fn runs_for_a_number_of_tokens_without_counting_the_end() {
+        let program = Program::parse("+>,").unwrap();
+        let mut interpreter = program.interpreter::<u8>(&RunConfig::default(), Tape::default()).unwrap();
+
+        assert!(matches!(interpreter.run_for(2), (Status::Running, 2)));
+        assert!(matches!(interpreter.run_for(5), (Status::NeedsInput, 0)));
+        interpreter.provide_input(Some(3));
+        assert!(matches!(interpreter.run_for(0), (Status::Halted, 0)));
+
+        let mut interpreter = program.interpreter::<u8>(&RunConfig::default(), Tape::default()).unwrap();
+        assert!(matches!(interpreter.run_for(1), (Status::Running, 1)));
+        assert!(matches!(interpreter.run_for(1), (Status::Running, 1)));
+        assert!(matches!(interpreter.run_for(0), (Status::Running, 0)));
+        assert_eq!(interpreter.instruction_pointer(), 2);
+    }
+
+    #[test]
+    fn stays_failed_after_an_error() {
+        let program = Program::parse("+<").unwrap();
+        let mut interpreter = program.interpreter::<u8>(&RunConfig::default(), Tape::default()).unwrap();
+
+        assert!(matches!(interpreter.step(), Status::Running));
+        assert!(matches!(interpreter.step(), Status::Error(Error::TapeUnderflow { ip: 1, dp: 0 })));
+        assert!(matches!(interpreter.step(), Status::Error(Error::TapeUnderflow { ip: 1, dp: 0 })));
+        assert_eq!(interpreter.instruction_pointer(), 1);
+    }
+
+    #[test]
+    fn rejects_an_empty_tape_size() {
+        let program = Program::parse("+").unwrap();
+        let config = RunConfig { tape_size: 0, ..RunConfig::default() };
+        assert!(matches!(program.interpreter::<u8>(&config, Tape::default()), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn rejects_invalid_tapes() {
+        let program = Program::parse("+").unwrap();
+        let config = RunConfig { tape_size: 4, ..RunConfig::default() };
+
+        let tape = Tape { cells: vec![0u8; 5], data_pointer: 0 };
+        assert!(matches!(program.interpreter(&config, tape), Err(Error::Config(_))));
+
+        let tape = Tape { cells: vec![0u8; 2], data_pointer: 4 };
+        assert!(matches!(program.interpreter(&config, tape), Err(Error::Config(_))));
+
+        let tape = Tape { cells: vec![0u8; 2], data_pointer: 3 };
+        assert!(program.interpreter(&config, tape).is_ok());
+    }
+
+    #[test]
+    fn rejects_data_pointers_past_the_padded_tape() {
+        let program = Program::parse("+").unwrap();
+        let tape = Tape { cells: vec![0u8; 2], data_pointer: 5000 };
+        assert!(matches!(program.interpreter(&RunConfig::default(), tape), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn stops_at_the_ends_of_the_tape() {
+        let config = RunConfig { tape_size: 3, ..RunConfig::default() };
+        assert!(run::<u8>(">>", &config, &[]).is_ok());
+        assert!(matches!(run::<u8>(">>>", &config, &[]), Err(Error::TapeOverflow { .. })));
+        assert!(matches!(run::<u8>("><<", &config, &[]), Err(Error::TapeUnderflow { .. })));
+    }
+
+    #[test]
+    fn shows_the_tape_around_the_data_pointer() {
+        let parse = ParseConfig { debug_char: Some('#'), ..ParseConfig::default() };
+        let program = Program::parse_with(">+>++#", &parse).unwrap();
+        let config = RunConfig { debug_window: 1, tape_size: 3, ..RunConfig::default() };
+        let mut interpreter = program.interpreter::<u8>(&config, Tape::default()).unwrap();
+
+        let status = loop {
+            match interpreter.step() {
+                Status::Running => {},
+                x => break x,
+            }
+        };
+        assert!(matches!(status, Status::Debug));
+        // The window stops at the end of the tape
+        assert_eq!(interpreter.show_tape(), "dp 2, tape[1..3] = 1 [2]");
+        assert!(matches!(interpreter.step(), Status::Halted));
+    }
+}
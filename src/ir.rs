@@ -0,0 +1,339 @@
+//! The `.bfc` format, which stores an optimized program so it can be run without reparsing.
+//!
+//! A `.bfc` file is laid out as follows, with all integers little-endian:
+//!
+//! | Bytes    | Contents                                                  |
+//! |----------|-----------------------------------------------------------|
+//! | 4        | The magic number `BFC\0`                                  |
+//! | 2        | The format version, currently 1                           |
+//! | 1        | The length `n` of the rustfuck version string             |
+//! | `n`      | The version of rustfuck which wrote the file, as UTF-8    |
+//! | 1        | The cell size in bits (8, 16, 32 or 64), or 0 for auto    |
+//! | 4        | The number of tokens                                      |
+//! | 5 each   | The tokens: a type byte followed by a 4-byte value        |
+//!
+//! Loop jumps are stored as they were linked, but are checked and linked again when loading.
+
+use std::str;
+use std::convert::TryInto;
+
+use crate::ast::{Ast, Token, TokenType};
+use crate::cell_size::CellWidth;
+use crate::error::Error;
+
+/// The first bytes of every `.bfc` file.
+pub const MAGIC: &[u8] = b"BFC\0";
+
+/// The version of the layout written by `write`.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// A program loaded from a `.bfc` file, along with its header.
+pub struct Compiled {
+    pub ast: Ast,
+    /// The version of rustfuck which wrote the file.
+    pub version: String,
+    /// The cell size the program was compiled for.
+    pub cell_width: CellWidth,
+}
+
+/// Whether the bytes look like a `.bfc` file rather than brainfuck source.
+pub fn is_ir(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub(crate) fn type_to_byte(tk: TokenType) -> u8 {
+    match tk {
+        TokenType::Invalid => 0,
+        TokenType::End => 1,
+        TokenType::Add => 2,
+        TokenType::Sub => 3,
+        TokenType::Left => 4,
+        TokenType::Right => 5,
+        TokenType::Out => 6,
+        TokenType::In => 7,
+        TokenType::LoopStart => 8,
+        TokenType::LoopEnd => 9,
+        TokenType::Set => 10,
+        TokenType::Move => 11,
+        TokenType::Debug => 12,
+    }
+}
+
+fn byte_to_type(byte: u8) -> Option<TokenType> {
+    match byte {
+        1 => Some(TokenType::End),
+        2 => Some(TokenType::Add),
+        3 => Some(TokenType::Sub),
+        4 => Some(TokenType::Left),
+        5 => Some(TokenType::Right),
+        6 => Some(TokenType::Out),
+        7 => Some(TokenType::In),
+        8 => Some(TokenType::LoopStart),
+        9 => Some(TokenType::LoopEnd),
+        10 => Some(TokenType::Set),
+        11 => Some(TokenType::Move),
+        12 => Some(TokenType::Debug),
+        _ => None,
+    }
+}
+
+/// Encodes an AST, which is expected to be linked, in the `.bfc` format.
+pub fn write(ast: &Ast, cell_width: CellWidth) -> Vec<u8> {
+    let version = env!("CARGO_PKG_VERSION");
+
+    let mut out = Vec::with_capacity(16 + version.len() + ast.len() * 5);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.push(version.len() as u8);
+    out.extend_from_slice(version.as_bytes());
+    out.push(match cell_width {
+        CellWidth::Bits(bits) => bits,
+        CellWidth::Auto => 0,
+    });
+    out.extend_from_slice(&(ast.len() as u32).to_le_bytes());
+
+    for token in ast {
+        out.push(type_to_byte(token.tk));
+        out.extend_from_slice(&token.value.to_le_bytes());
+    }
+
+    out
+}
+
+/// Reads bytes from the front of a `.bfc` file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < n {
+            return Err(Error::InvalidIr("Compiled program is truncated"));
+        }
+
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Decodes a `.bfc` file.
+///
+/// The tokens are checked so that they can be run safely: the program must end with a single
+/// `End` token, its loops must be balanced, and counts and offsets must be in range. `Set` values
+/// must fit in 8 bits, so that they mean the same at every cell size and automatic width detection
+/// never has to check them. Loop jumps are left for the caller to link again.
+pub fn read(bytes: &[u8]) -> Result<Compiled, Error> {
+    let mut reader = Reader { bytes };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(Error::InvalidIr("Not a compiled program"));
+    }
+    if reader.u16()? != FORMAT_VERSION {
+        return Err(Error::InvalidIr("Unsupported compiled program format version"));
+    }
+
+    let version_len = reader.u8()? as usize;
+    let version = match String::from_utf8(reader.take(version_len)?.to_vec()) {
+        Ok(x) => x,
+        Err(_) => return Err(Error::InvalidIr("Compiled program has an invalid version string")),
+    };
+
+    let cell_width = match reader.u8()? {
+        0 => CellWidth::Auto,
+        bits @ (8 | 16 | 32 | 64) => CellWidth::Bits(bits),
+        _ => return Err(Error::InvalidIr("Compiled program has an unsupported cell size")),
+    };
+
+    let count = reader.u32()? as usize;
+    let mut ast = Ast::with_capacity(count.min(reader.bytes.len() / 5));
+    let mut depth: usize = 0;
+    for i in 0..count {
+        let tk = match byte_to_type(reader.u8()?) {
+            Some(x) => x,
+            None => return Err(Error::InvalidIr("Compiled program contains an unknown token")),
+        };
+        let value = reader.i32()?;
+
+        match tk {
+            TokenType::End if i + 1 != count => {
+                return Err(Error::InvalidIr("Compiled program has tokens after the end"));
+            },
+            TokenType::LoopStart => depth += 1,
+            TokenType::LoopEnd if depth == 0 => {
+                return Err(Error::InvalidIr("Compiled program has unbalanced loops"));
+            },
+            TokenType::LoopEnd => depth -= 1,
+            TokenType::Left | TokenType::Right | TokenType::Out if value < 0 => {
+                return Err(Error::InvalidIr("Compiled program contains an invalid token"));
+            },
+            TokenType::Move if value == 0 => {
+                return Err(Error::InvalidIr("Compiled program contains an out of range move"));
+            },
+            TokenType::Set if !(0..=255).contains(&value) => {
+                return Err(Error::InvalidIr("Compiled program sets a cell out of range"));
+            },
+            _ => {},
+        }
+
+        ast.push(Token::new(tk, value));
+    }
+
+    if depth != 0 {
+        return Err(Error::InvalidIr("Compiled program has unbalanced loops"));
+    }
+    match ast.last() {
+        Some(token) if token.tk == TokenType::End => {},
+        _ => return Err(Error::InvalidIr("Compiled program has no end")),
+    }
+    if !reader.bytes.is_empty() {
+        return Err(Error::InvalidIr("Compiled program has trailing data"));
+    }
+
+    Ok(Compiled {
+        ast,
+        version,
+        cell_width,
+    })
+}
+
+/// What `--emit` writes instead of running the program.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Emit {
+    /// The optimized program in the `.bfc` format.
+    Ir,
+}
+
+impl str::FromStr for Emit {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Emit, &'static str> {
+        match s {
+            "ir" => Ok(Emit::Ir),
+            _ => Err("Unknown emit format"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::RunConfig;
+    use crate::optimize::OptConfig;
+    use crate::stream::MemoryIo;
+    use crate::Program;
+
+    fn tokens(tokens: &[(TokenType, i32)]) -> Ast {
+        tokens.iter().map(|&(tk, value)| Token::new(tk, value)).collect()
+    }
+
+    fn read_err(ast: &Ast) -> &'static str {
+        match read(&write(ast, CellWidth::Bits(8))) {
+            Err(Error::InvalidIr(x)) => x,
+            Err(x) => panic!("unexpected error {}", x),
+            Ok(_) => panic!("expected the program to be rejected"),
+        }
+    }
+
+    #[test]
+    fn round_trips_optimized_programs() {
+        let mut program = Program::parse("++[->+++<]>.,[-]").unwrap();
+        program.optimize(&OptConfig::default());
+
+        let bytes = program.to_ir(CellWidth::Bits(16));
+        assert!(is_ir(&bytes));
+
+        let (loaded, width) = Program::from_ir(&bytes).unwrap();
+        assert_eq!(width, CellWidth::Bits(16));
+        assert_eq!(loaded.tokens().len(), program.tokens().len());
+        for (a, b) in loaded.tokens().iter().zip(program.tokens()) {
+            assert_eq!((a.tk, a.value), (b.tk, b.value));
+        }
+
+        let mut io = MemoryIo::new(&[]);
+        loaded.run::<u16, _>(&RunConfig::default(), &mut io).unwrap();
+        assert_eq!(io.output, [6]);
+    }
+
+    #[test]
+    fn records_automatic_cell_width() {
+        let program = Program::parse("+").unwrap();
+        let (_, width) = Program::from_ir(&program.to_ir(CellWidth::Auto)).unwrap();
+        assert_eq!(width, CellWidth::Auto);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let bytes = write(&tokens(&[(TokenType::End, 0)]), CellWidth::Bits(8));
+
+        assert!(matches!(read(b"BFD\0"), Err(Error::InvalidIr(_))));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 2;
+        assert!(matches!(read(&wrong_version), Err(Error::InvalidIr(_))));
+
+        assert!(matches!(read(&bytes[..bytes.len() - 1]), Err(Error::InvalidIr(_))));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(read(&trailing), Err(Error::InvalidIr(_))));
+
+        // The cell size comes straight after the version string
+        let mut odd_width = bytes;
+        let at = 7 + odd_width[6] as usize;
+        assert_eq!(odd_width[at], 8);
+        odd_width[at] = 12;
+        assert!(matches!(read(&odd_width), Err(Error::InvalidIr("Compiled program has an unsupported cell size"))));
+    }
+
+    #[test]
+    fn rejects_programs_which_cant_run_safely() {
+        use TokenType::*;
+
+        assert_eq!(read_err(&tokens(&[(Add, 1)])), "Compiled program has no end");
+        assert_eq!(read_err(&tokens(&[(End, 0), (Add, 1), (End, 0)])), "Compiled program has tokens after the end");
+        assert_eq!(read_err(&tokens(&[(LoopStart, 0), (End, 0)])), "Compiled program has unbalanced loops");
+        assert_eq!(read_err(&tokens(&[(LoopEnd, 0), (End, 0)])), "Compiled program has unbalanced loops");
+        assert_eq!(read_err(&tokens(&[(Left, -1), (End, 0)])), "Compiled program contains an invalid token");
+        assert_eq!(read_err(&tokens(&[(Move, 0), (End, 0)])), "Compiled program contains an out of range move");
+        assert_eq!(read_err(&tokens(&[(Set, 256), (End, 0)])), "Compiled program sets a cell out of range");
+        assert_eq!(read_err(&tokens(&[(Set, -1), (End, 0)])), "Compiled program sets a cell out of range");
+
+        let mut unknown = write(&tokens(&[(End, 0)]), CellWidth::Bits(8));
+        let at = unknown.len() - 5;
+        unknown[at] = 200;
+        assert!(matches!(read(&unknown), Err(Error::InvalidIr("Compiled program contains an unknown token"))));
+    }
+
+    #[test]
+    fn moves_far_off_the_tape_fail_cleanly() {
+        use TokenType::*;
+
+        let run = |offset| {
+            let bytes = write(&tokens(&[(Right, 5), (Add, 1), (Move, offset), (End, 0)]), CellWidth::Bits(8));
+            let (program, _) = Program::from_ir(&bytes).unwrap();
+            program.run::<u8, _>(&RunConfig::default(), &mut MemoryIo::new(&[])).map(|_| ())
+        };
+
+        assert!(matches!(run(i32::MAX), Err(Error::TapeOverflow { ip: 2, dp: 5 })));
+        assert!(matches!(run(i32::MIN), Err(Error::TapeUnderflow { ip: 2, dp: 5 })));
+        assert!(matches!(run(-6), Err(Error::TapeUnderflow { ip: 2, dp: 5 })));
+        assert!(run(-5).is_ok());
+    }
+}
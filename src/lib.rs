@@ -0,0 +1,208 @@
+//! An optimizing interpreter for brainfuck.
+//!
+//! Source code is parsed into a [`Program`], which can be optimized and then run with cells of
+//! any [`CellSize`], talking to the outside world through any [`BfIo`].
+//!
+//! ```
+//! use rustfuck::{Program, OptConfig, RunConfig};
+//! use rustfuck::stream::{StreamIo, Input, Output, Flush, IoMode};
+//!
+//! let mut program = Program::parse("++++++++[>++++++++<-]>+.").unwrap();
+//! program.optimize(&OptConfig::default());
+//!
+//! let mut out = Vec::new();
+//! {
+//!     let input = Input::new(&b""[..], None, false);
+//!     let mut io = StreamIo::new(input, Output::new(&mut out, Flush::Exit), IoMode::Bytes);
+//!     program.run::<u8, _>(&RunConfig::default(), &mut io).unwrap();
+//! }
+//! assert_eq!(out, b"A");
+//! ```
+
+pub mod ast;
+pub mod cell_size;
+pub mod diagnostic;
+pub mod error;
+pub mod ffi;
+pub mod interpreter;
+pub mod ir;
+// Only public so the binary can share it, and not part of the API
+#[doc(hidden)]
+pub mod json;
+pub mod lint;
+pub mod stream;
+pub mod tape;
+
+mod execute;
+mod optimize;
+mod parse;
+#[cfg(test)]
+mod temp;
+#[cfg(test)]
+mod testing;
+
+pub use ast::{Ast, AstFormat, Dumpable, DumpView, Span, Token, TokenType};
+pub use cell_size::CellSize;
+pub use error::Error;
+pub use execute::{drive, drive_for, AutoWidth, Drive, Halt, RunConfig};
+pub use interpreter::{Interpreter, Status};
+pub use optimize::OptConfig;
+pub use parse::ParseConfig;
+pub use tape::Tape;
+
+use cell_size::CellWidth;
+use stream::BfIo;
+
+/// A parsed brainfuck program.
+pub struct Program {
+    ast: Ast,
+}
+
+impl Program {
+    /// Parses brainfuck source code. Characters other than the eight commands are ignored.
+    ///
+    /// Fails if the program's brackets don't match up.
+    pub fn parse(source: &str) -> Result<Program, Error> {
+        Program::parse_with(source, &ParseConfig::default())
+    }
+
+    /// Parses brainfuck source code, with the comment syntax and strictness set in `config`.
+    ///
+    /// Fails if the program's brackets don't match up, or it has stray characters in strict mode.
+    pub fn parse_with(source: &str, config: &ParseConfig) -> Result<Program, Error> {
+        let mut ast = parse::parse(source, config)?;
+        parse::link_loops(&mut ast)?;
+
+        Ok(Program { ast })
+    }
+
+    /// Loads a program compiled by `to_ir`, along with the cell size it was compiled for.
+    pub fn from_ir(bytes: &[u8]) -> Result<(Program, CellWidth), Error> {
+        let compiled = ir::read(bytes)?;
+        let mut ast = compiled.ast;
+        parse::link_loops(&mut ast)?;
+
+        Ok((Program { ast }, compiled.cell_width))
+    }
+
+    /// Encodes the program in the `.bfc` format, recording the cell size it should be run with.
+    pub fn to_ir(&self, cell_width: CellWidth) -> Vec<u8> {
+        ir::write(&self.ast, cell_width)
+    }
+
+    /// Runs the optimizer passes enabled in `config`.
+    pub fn optimize(&mut self, config: &OptConfig) {
+        optimize::optimize(&mut self.ast, config);
+
+        // Passes move tokens around, so loop jumps have to be linked again
+        parse::link_loops(&mut self.ast).expect("optimizer passes keep loops balanced");
+    }
+
+    /// The program's tokens in the intermediate representation.
+    pub fn tokens(&self) -> &[Token] {
+        &self.ast
+    }
+
+    /// Formats the program's tokens as human-readable text.
+    pub fn dump(&self) -> String {
+        self.ast.dump()
+    }
+
+    /// Formats the program's tokens in the given format, using `view` to lay out plain text.
+    /// `source` is the code the program was parsed from, which is shown alongside the tokens.
+    pub fn dump_as(&self, format: AstFormat, view: DumpView, source: &str) -> String {
+        match (format, view) {
+            (AstFormat::Text, DumpView::Tokens) => self.ast.dump(),
+            (AstFormat::Text, DumpView::Annotated) => ast::dump_annotated(&self.ast, source),
+            (AstFormat::Text, DumpView::SideBySide) => ast::dump_side_by_side(&self.ast, source),
+            (AstFormat::Json, _) => ast::dump_json(&self.ast, source),
+            (AstFormat::Dot, _) => ast::dump_dot(&self.ast),
+            (AstFormat::Html, _) => ast::dump_html(&self.ast, source),
+        }
+    }
+
+    /// Creates an interpreter which runs the program a step at a time, starting from the given
+    /// tape.
+    pub fn interpreter<T>(&self, config: &RunConfig, tape: Tape<T>) -> Result<Interpreter<'_, T>, Error>
+    where T: CellSize + Clone + Copy
+    {
+        Interpreter::new(&self.ast, config, tape)
+    }
+
+    /// Runs the program with cells of type `T`, starting from an empty tape.
+    pub fn run<T, I>(&self, config: &RunConfig, io: &mut I) -> Result<Halt<T>, Error>
+    where T: CellSize + Clone + Copy,
+          I: BfIo<T>,
+    {
+        self.run_with_tape(config, Tape::default(), io)
+    }
+
+    /// Runs the program with cells of type `T`, starting from the given tape.
+    pub fn run_with_tape<T, I>(&self, config: &RunConfig, tape: Tape<T>, io: &mut I) -> Result<Halt<T>, Error>
+    where T: CellSize + Clone + Copy,
+          I: BfIo<T>,
+    {
+        execute::run(&self.ast, config, tape, io)
+    }
+
+    /// Runs the program starting from the given tape, choosing between 8-bit and 64-bit cells
+    /// depending on whether it relies on cells wrapping below zero. Fails with
+    /// `Error::AmbiguousCellSize` if a loop relies on a wide cell wrapping back round to zero.
+    pub fn run_auto<I>(&self, config: &RunConfig, tape: Tape<u64>, io: &mut I) -> Result<AutoWidth, Error>
+    where I: BfIo<u64> + BfIo<u8>
+    {
+        execute::run_auto(&self.ast, config, tape, io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::MemoryIo;
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    fn output<T>(program: &Program) -> Vec<T>
+    where T: CellSize + Clone + Copy
+    {
+        let mut io = MemoryIo::new(&[]);
+        program.run::<T, _>(&RunConfig::default(), &mut io).unwrap();
+        io.output
+    }
+
+    #[test]
+    fn optimizing_keeps_the_output() {
+        let mut program = Program::parse(HELLO).unwrap();
+        let plain = output::<u8>(&program);
+        assert_eq!(plain, b"Hello World!\n");
+
+        let len = program.tokens().len();
+        program.optimize(&OptConfig::default());
+        assert!(program.tokens().len() < len);
+        assert_eq!(output::<u8>(&program), plain);
+    }
+
+    #[test]
+    fn runs_with_every_cell_size() {
+        let program = Program::parse("-.").unwrap();
+        assert_eq!(output::<u8>(&program), [u8::MAX]);
+        assert_eq!(output::<u16>(&program), [u16::MAX]);
+        assert_eq!(output::<u32>(&program), [u32::MAX]);
+        assert_eq!(output::<u64>(&program), [u64::MAX]);
+    }
+
+    #[test]
+    fn runs_from_a_given_tape() {
+        let program = Program::parse("[.-]").unwrap();
+        let mut io = MemoryIo::new(&[]);
+        let tape = Tape { cells: vec![3u8], data_pointer: 0 };
+        program.run_with_tape(&RunConfig::default(), tape, &mut io).unwrap();
+        assert_eq!(io.output, [3, 2, 1]);
+    }
+
+    #[test]
+    fn rejects_unmatched_brackets() {
+        assert!(matches!(Program::parse("[[]"), Err(Error::Syntax(_))));
+        assert!(matches!(Program::parse("[]]"), Err(Error::Syntax(_))));
+    }
+}
@@ -0,0 +1,308 @@
+//! Static checks for code which is probably a mistake, used by `rustfuck check`.
+
+use std::collections::HashMap;
+
+use crate::ast::{Ast, Span, Token, TokenType};
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+use crate::parse::{self, ParseConfig};
+
+/// Checks source code, returning errors for unmatched brackets or stray characters, or else
+/// warnings about suspicious code, in source order.
+///
+/// Fails if `config` isn't valid.
+pub fn check(source: &str, config: &ParseConfig) -> Result<Vec<Diagnostic>, Error> {
+    let mut ast = match parse::parse(source, config) {
+        Ok(x) => x,
+        Err(Error::Syntax(diagnostics)) => return Ok(diagnostics),
+        Err(x) => return Err(x),
+    };
+    match parse::link_loops(&mut ast) {
+        Ok(_) => {},
+        Err(Error::Syntax(diagnostics)) => return Ok(diagnostics),
+        Err(_) => unreachable!("link_loops only fails with syntax errors"),
+    }
+
+    let mut diagnostics = Vec::new();
+    lint_cancelling(&ast, &mut diagnostics);
+    lint_loops(&ast, &mut diagnostics);
+
+    // Loops which are never entered are usually comments, so they and anything in them are left
+    // alone
+    let comments = lint_known_cells(&ast, &mut diagnostics);
+    diagnostics.retain(|d| !comments.iter().any(|c| c.start <= d.span.start && d.span.end <= c.end));
+
+    diagnostics.sort_by_key(|d| d.span.start);
+    Ok(diagnostics)
+}
+
+/// The span covering the tokens from `start` to `end`, inclusive.
+fn span_of(ast: &Ast, start: usize, end: usize) -> Span {
+    ast[start].span.merge(&ast[end].span)
+}
+
+/// Warns about runs like `+-` and `<>`, where commands undo each other.
+fn lint_cancelling(ast: &Ast, diagnostics: &mut Vec<Diagnostic>) {
+    let pairs = [
+        (TokenType::Add, TokenType::Sub, '+', '-'),
+        (TokenType::Right, TokenType::Left, '>', '<'),
+    ];
+
+    for &(up, down, up_char, down_char) in pairs.iter() {
+        let mut i = 0;
+        while i < ast.len() {
+            if ast[i].tk != up && ast[i].tk != down {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            let mut net: i32 = 0;
+            let mut mixed = false;
+            while i < ast.len() && (ast[i].tk == up || ast[i].tk == down) {
+                net += if ast[i].tk == up { ast[i].value } else { -ast[i].value };
+                mixed |= ast[i].tk != ast[start].tk;
+                i += 1;
+            }
+
+            if mixed {
+                let label = match net {
+                    0 => String::from("these cancel each other out"),
+                    n if n > 0 => format!("same as {}{}", up_char, n),
+                    n => format!("same as {}{}", down_char, -n),
+                };
+                let message = format!("`{}` and `{}` cancel out", up_char, down_char);
+                diagnostics.push(Diagnostic::warning(&message, span_of(ast, start, i - 1), &label));
+            }
+        }
+    }
+}
+
+/// Warns about empty loops, and innermost loops which move the data pointer as well as doing
+/// something else. Loops which only move the data pointer, like `[>]`, are idiomatic scans and
+/// aren't reported.
+fn lint_loops(ast: &Ast, diagnostics: &mut Vec<Diagnostic>) {
+    for (i, token) in ast.iter().enumerate() {
+        if token.tk != TokenType::LoopStart {
+            continue;
+        }
+
+        let end = token.value as usize;
+        let body = &ast[i + 1..end];
+        if body.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                "Empty loop", span_of(ast, i, end), "never ends if the current cell isn't zero"
+            ));
+            continue;
+        }
+
+        if body.iter().any(|t| t.tk == TokenType::LoopStart) {
+            continue;
+        }
+
+        let net: i32 = body.iter()
+            .map(|t| match t.tk {
+                TokenType::Right => t.value,
+                TokenType::Left => -t.value,
+                _ => 0,
+            })
+            .sum();
+        let only_moves = body.iter().all(|t| t.tk == TokenType::Right || t.tk == TokenType::Left);
+
+        if net != 0 && !only_moves {
+            let direction = if net > 0 { "right" } else { "left" };
+            let cells = if net.abs() == 1 { "cell" } else { "cells" };
+            let label = format!("moves the data pointer {} {} {} each time round", net.abs(), cells, direction);
+            diagnostics.push(Diagnostic::warning("Unbalanced loop", span_of(ast, i, end), &label));
+        }
+    }
+}
+
+/// What's known about the tape while following the program from the start.
+struct KnownCells {
+    /// The data pointer, relative to where tracking started.
+    dp: i64,
+    /// Whether `dp` is the actual cell index, because no loop has run yet.
+    absolute: bool,
+    /// Cells with known values, or `None` for cells known to be unknown.
+    cells: HashMap<i64, Option<i64>>,
+    /// Whether cells which aren't in `cells` are zero, rather than unknown.
+    rest_zero: bool,
+}
+
+impl KnownCells {
+    fn get(&self) -> Option<i64> {
+        match self.cells.get(&self.dp) {
+            Some(x) => *x,
+            None if self.rest_zero => Some(0),
+            None => None,
+        }
+    }
+
+    fn add(&mut self, amount: i64) {
+        let value = self.get().map(|v| v + amount);
+        // A nonzero multiple of 256 might have wrapped to zero, depending on the cell size
+        let value = value.filter(|v| *v == 0 || v.rem_euclid(256) != 0);
+        self.cells.insert(self.dp, value);
+    }
+}
+
+/// Whether a loop runs forever once it's entered: it has no nested loops or input, leaves the
+/// data pointer where it was, and never changes the cell it tests.
+fn never_exits(body: &[Token]) -> bool {
+    let mut offset = 0;
+    for token in body {
+        match token.tk {
+            TokenType::LoopStart | TokenType::In => return false,
+            TokenType::Add | TokenType::Sub if offset == 0 => return false,
+            TokenType::Right => offset += token.value,
+            TokenType::Left => offset -= token.value,
+            _ => {},
+        }
+    }
+
+    offset == 0
+}
+
+/// Follows the program from the start while cell values can be worked out, warning about `<`
+/// which moves off the start of the tape, and code after a loop which is entered but never exits.
+///
+/// Returns the spans of loops which are never entered.
+fn lint_known_cells(ast: &Ast, diagnostics: &mut Vec<Diagnostic>) -> Vec<Span> {
+    let mut skipped = Vec::new();
+    let mut known = KnownCells {
+        dp: 0,
+        absolute: true,
+        cells: HashMap::new(),
+        rest_zero: true,
+    };
+
+    let mut i = 0;
+    while i < ast.len() {
+        let token = &ast[i];
+        match token.tk {
+            TokenType::Add => known.add(token.value as i64),
+            TokenType::Sub => known.add(-token.value as i64),
+            TokenType::Right => known.dp += token.value as i64,
+            TokenType::Left => {
+                known.dp -= token.value as i64;
+                if known.absolute && known.dp < 0 {
+                    diagnostics.push(Diagnostic::warning(
+                        "Tape underflow", token.span, "moves the data pointer left of the first cell"
+                    ));
+                    // The position isn't meaningful any more, so don't report this again
+                    known.absolute = false;
+                }
+            },
+            TokenType::In => {
+                known.cells.insert(known.dp, None);
+            },
+            TokenType::LoopStart => {
+                let end = token.value as usize;
+                match known.get() {
+                    // Never entered
+                    Some(0) => {
+                        skipped.push(span_of(ast, i, end));
+                        i = end + 1;
+                        continue;
+                    },
+                    Some(_) if never_exits(&ast[i + 1..end]) => {
+                        let last = ast.len() - 2;
+                        if end < last {
+                            diagnostics.push(
+                                Diagnostic::warning("Unreachable code", span_of(ast, end + 1, last), "this code never runs")
+                                    .with_related(span_of(ast, i, end), "this loop never ends")
+                            );
+                        }
+                        return skipped;
+                    },
+                    _ => {
+                        // Afterwards, all that's known is that the current cell is zero
+                        known = KnownCells {
+                            dp: 0,
+                            absolute: false,
+                            cells: HashMap::new(),
+                            rest_zero: false,
+                        };
+                        known.cells.insert(0, Some(0));
+                        i = end + 1;
+                        continue;
+                    },
+                }
+            },
+            _ => {},
+        }
+
+        i += 1;
+    }
+
+    skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message, position and label of each diagnostic for `source`.
+    fn lint(source: &str) -> Vec<(String, usize, String)> {
+        check(source, &ParseConfig::default()).unwrap()
+            .iter()
+            .map(|d| (d.message.clone(), d.span.start, d.label.clone()))
+            .collect()
+    }
+
+    fn warning(message: &str, start: usize, label: &str) -> (String, usize, String) {
+        (message.to_string(), start, label.to_string())
+    }
+
+    #[test]
+    fn warns_about_cancelling_commands() {
+        assert_eq!(lint("+-,"), [warning("`+` and `-` cancel out", 0, "these cancel each other out")]);
+        assert_eq!(lint("++-+,"), [warning("`+` and `-` cancel out", 0, "same as +2")]);
+        assert_eq!(lint(">><<<<+"), [
+            warning("`>` and `<` cancel out", 0, "same as <2"),
+            warning("Tape underflow", 4, "moves the data pointer left of the first cell"),
+        ]);
+    }
+
+    #[test]
+    fn warns_about_tape_underflow_once() {
+        assert_eq!(lint("<+<"), [warning("Tape underflow", 0, "moves the data pointer left of the first cell")]);
+        // Once a loop has run, the position isn't known
+        assert!(lint(",[>]<<").is_empty());
+    }
+
+    #[test]
+    fn warns_about_loops() {
+        assert_eq!(lint(",[]"), [warning("Empty loop", 1, "never ends if the current cell isn't zero")]);
+        assert_eq!(lint(",[->>+<]"), [warning("Unbalanced loop", 1, "moves the data pointer 1 cell right each time round")]);
+        assert_eq!(lint(",[<<.]"), [warning("Unbalanced loop", 1, "moves the data pointer 2 cells left each time round")]);
+        // Scans and balanced loops are idiomatic
+        assert!(lint(",[>],[->+<]").is_empty());
+    }
+
+    #[test]
+    fn warns_about_code_after_an_endless_loop() {
+        let diagnostics = check("+[>.<]+.", &ParseConfig::default()).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Unreachable code");
+        assert_eq!((diagnostics[0].span.start, diagnostics[0].span.end), (6, 8));
+        assert_eq!(diagnostics[0].related[0].1, "this loop never ends");
+    }
+
+    #[test]
+    fn ignores_comment_loops() {
+        assert!(lint("[+-<<[]] this is a comment, really.").is_empty());
+        // Including warnings about the comment loop itself
+        assert!(lint("[] empty comment").is_empty());
+        assert!(lint("[comment, <<. ] +.").is_empty());
+    }
+
+    #[test]
+    fn reports_syntax_errors_instead() {
+        let diagnostics = check("+-[", &ParseConfig::default()).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Unmatched [");
+        assert_eq!(diagnostics[0].severity, crate::diagnostic::Severity::Error);
+    }
+}
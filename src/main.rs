@@ -1,574 +1,451 @@
 extern crate argparse;
-use argparse::{ArgumentParser, Store, StoreFalse, StoreTrue};
-
-mod cell_size;
-use cell_size::{CellSize};
-
-use std::{str, fs, io};
-use io::{Write, Read};
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum TokenType {
-    Invalid,
-    End,
-    Add,
-    Sub,
-    Left,
-    Right,
-    Out,
-    In,
-    LoopStart,
-    LoopEnd,
-    Set,
-    Move,
-}
+use argparse::{ArgumentParser, Store, StoreFalse, StoreTrue, StoreOption};
+
+extern crate rustfuck;
+
+mod batch;
+mod check;
+mod debug;
+mod profile;
+mod trace;
+#[cfg(test)]
+#[path = "temp.rs"]
+mod temp;
+use rustfuck::{Program, AstFormat, DumpView, OptConfig, ParseConfig, RunConfig, Halt, AutoWidth, Tape, CellSize, Error};
+use rustfuck::cell_size::{CellWidth};
+use rustfuck::diagnostic::ColorMode;
+use rustfuck::ir::{self, Emit};
+use rustfuck::tape::{self, TapeOptions, InitFormat, DumpFormat};
+use rustfuck::stream::{BfIo, Flush, IoMode, IoOptions};
+use profile::ProfileOptions;
+use trace::{TraceFormat, TraceOptions};
+
+use std::{env, fs, io, process};
+use std::convert::TryInto;
+use std::path::Path;
+use io::{Read, Write};
+
+/// Loads the initial tape from the file given to `--tape-init`, if any.
+fn load_tape<T>(config: &RunConfig, tape_options: &TapeOptions) -> Result<Tape<T>, Error>
+where T: CellSize
+{
+    let cells = match &tape_options.init {
+        Some(filename) => tape::load(filename, tape_options.init_format, config.tape_size)?,
+        None => Vec::new(),
+    };
 
-#[derive(Debug)]
-struct Token {
-    pub tk: TokenType,
-    pub value: i32,
+    Ok(Tape { cells, data_pointer: 0 })
 }
 
-impl Token {
-    fn new(tk: TokenType, value: i32) -> Token {
-        Token {
-            tk,
-            value,
+/// Writes the final tape to the file given to `--dump-tape`, if any.
+fn dump_tape<T>(tape: &Tape<T>, tape_options: &TapeOptions) -> Result<(), Error>
+where T: CellSize
+{
+    if let Some(filename) = &tape_options.dump {
+        let out = tape::dump(tape, tape_options.dump_format);
+        if let Err(x) = fs::write(filename, out) {
+            return Err(Error::Io("Could not write tape dump file", x));
         }
     }
-}
-
-type Ast = Vec<Token>;
 
-trait Dumpable {
-    fn dump(&self) -> String;
+    Ok(())
 }
 
-impl Dumpable for Ast {
-    fn dump(&self) -> String {
-        let mut out = String::new();
-        let mut depth = 0;
-        let mut line = String::new();
-        for token in self.iter() {
-            let mut end_line = false;
-
-            let mut part: String = match token.tk {
-                TokenType::Add => {
-                    if token.value == 1 {
-                        "+".to_string()
-                    } else {
-                        format!("+{}", token.value)
-                    }
-                },
-                TokenType::Sub => {
-                    if token.value == 1 {
-                        "-".to_string()
-                    } else {
-                        format!("-{}", token.value)
-                    }
-                },
-                TokenType::Left => {
-                    if token.value == 1 {
-                        "<".to_string()
-                    } else {
-                        format!("<{}", token.value)
-                    }
-                },
-                TokenType::Right => {
-                    if token.value == 1 {
-                        ">".to_string()
-                    } else {
-                        format!(">{}", token.value)
-                    }
-                },
-                TokenType::In => {
-                    ",".to_string()
-                },
-                TokenType::Out => {
-                    ".".to_string()
-                },
-                TokenType::LoopStart => {
-                    end_line = true;
-                    "[".to_string()
-                },
-                TokenType::LoopEnd => {
-                    end_line = true;
-                    "]".to_string()
-                },
-                TokenType::Set => {
-                    format!("S{}", token.value)
-                },
-                TokenType::Move => {
-                    format!("M{}", token.value)
-                },
-                TokenType::Invalid => {
-                    "INVALID".to_string()
-                },
-                TokenType::End => {
-                    ":".to_string()
-                },
-            };
-
-            part.push(' ');
-
-            if ! end_line {
-                line.push_str(&part);
-            }
-
-            if line.len() >= 80 || end_line {
-                out.push_str(
-                    &format!("{}{}\n",
-                        "  ".repeat(depth),
-                        line
-                    )
-                );
-                line.clear();
-            }
-
-            if token.tk == TokenType::LoopEnd {
-                depth -= 1;
-            }
-
-            if end_line {
-                out.push_str(
-                    &format!("{}{}\n",
-                        "  ".repeat(depth),
-                        part
-                    )
-                );
-            }
-
-            if token.tk == TokenType::LoopStart {
-                depth += 1;
-            }
-        }
-
-        out
+/// Dumps the final tape, and reports where the program was stopped if `--max-output` stopped it.
+fn finish<T, I>(halt: Halt<T>, tape_options: &TapeOptions, io: &I) -> Result<(), Error>
+where T: CellSize,
+      I: BfIo<T>,
+{
+    match halt {
+        Halt::End(tape) => dump_tape(&tape, tape_options),
+        Halt::OutputLimit(tape, ip) => {
+            dump_tape(&tape, tape_options)?;
+            eprintln!(
+                "Output limit reached at instruction {}, data pointer {}, after writing {} bytes",
+                ip, tape.data_pointer, io.written()
+            );
+            Err(Error::OutputLimit)
+        },
     }
 }
 
-/// Parses raw text into an intermediate representation.
-fn parse(raw: &String) -> Result<Ast, &'static str> {
-    let mut ast = Ast::new();
-    let mut chars = raw.chars();
-
-    let res = loop {
-        let c = chars.next();
-        match c {
-            Some('+') => {
-                ast.push(
-                    Token::new(TokenType::Add, 1)
-                );
-            },
-            Some('-') => {
-                ast.push(
-                    Token::new(TokenType::Sub, 1)
-                );
-            },
-            Some('>') => {
-                ast.push(
-                    Token::new(TokenType::Right, 1)
-                );
-            },
-            Some('<') => {
-                ast.push(
-                    Token::new(TokenType::Left, 1)
-                );
-            },
-            Some('.') => {
-                ast.push(
-                    Token::new(TokenType::Out, 0)
-                );
-            },
-            Some(',') => {
-                ast.push(
-                    Token::new(TokenType::In, 0)
-                );
-            },
-            Some('[') => {
-                ast.push(
-                    Token::new(TokenType::LoopStart, -1)
-                );
-            },
-            Some(']') => {
-                ast.push(
-                    Token::new(TokenType::LoopEnd, -1)
-                );
-            },
-            None => {
-                break Ok(())
-            },
-            _ => {},
-        }
-    };
-
-    ast.push(
-        Token::new(TokenType::End, 0)
-    );
+/// The 64-bit FNV-1a hash of `bytes`, which unlike `DefaultHasher` is the same on every run and
+/// every platform.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
 
-    match res {
-        Ok(_) => Ok(ast),
-        Err(x) => Err(x),
+/// Splits a compile cache entry into its key and `.bfc` program, returning the program if the key
+/// is `key`. Two keys can hash to the same file name, so the name alone isn't enough.
+fn cache_entry<'a>(bytes: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let len = u32::from_le_bytes(bytes.get(..4)?.try_into().unwrap()) as usize;
+    let rest = &bytes[4..];
+    if rest.len() < len {
+        return None;
     }
-}
 
-/// A vector of replacements to be made with form:
-/// `(begin, end, replacement)`
-/// where `begin` is included and `end` is excluded.
-type ReplaceVec = Vec::<(usize, usize, Token)>;
-
-/// Given a sorted vector of replacements to be made, replace the given
-/// ranges of tokens with a single other token in the AST.
-fn replace_in_ast(ast: &mut Ast, mut replacements: ReplaceVec) {
-    replacements.reverse();
-    for (start, end, token) in replacements {
-        ast.drain(start..end);
-        ast.insert(start, token);
+    let (stored, ir) = rest.split_at(len);
+    if stored == key.as_bytes() {
+        Some(ir)
+    } else {
+        None
     }
 }
 
-/// Collapses duplicated tokens into a single token.
+/// Parses and optimizes the source code, going through the compile cache in `cache_dir` if given.
 ///
-/// e.g. `------`, which is represented as six `TokenType::Sub` with value `1`,
-/// is replaced by a single `TokenType::Sub` with value `6`. This applies to `-`, `+`, `>`, and `<`.
-fn pass_collapse_duplicated(ast: &mut Ast) {
-    let mut start: usize = 0;
-    let mut count: usize = 0;
-    let mut current = TokenType::Invalid;
-    let mut replace = ReplaceVec::new();
-    for (i, node) in ast.iter().enumerate() {
-        if node.tk == current {
-            count += 1;
-        } else {
-            if count > 1 {
-                replace.push((start, i, Token::new(current, count as i32)));
-            }
-
-            if node.tk == TokenType::Add
-            || node.tk == TokenType::Sub
-            || node.tk == TokenType::Left
-            || node.tk == TokenType::Right {
-                start = i;
-                count = 1;
-                current = node.tk;
-            } else {
-                count = 0;
-                current = TokenType::Invalid;
+/// Cache entries are `.bfc` files named after a hash of the source, the parser and optimizer
+/// settings and the rustfuck version. Each entry starts with those in full, as a 4-byte
+/// little-endian length and the text, and is only used if they match. Entries which can't be
+/// loaded are compiled again and overwritten. A cache which can't be written to is skipped with a
+/// warning.
+fn compile(source: &str, parse: &ParseConfig, opt: Option<&OptConfig>, cache_dir: &Option<String>, cell_width: CellWidth) -> Result<Program, Error> {
+    let key = format!("{}\n{:?}\n{:?}\n{}", env!("CARGO_PKG_VERSION"), parse, opt, source);
+    let path = cache_dir.as_ref()
+        .map(|dir| Path::new(dir).join(format!("{:016x}.bfc", fnv1a(key.as_bytes()))));
+
+    if let Some(path) = &path {
+        if let Ok(bytes) = fs::read(path) {
+            if let Some(ir) = cache_entry(&bytes, &key) {
+                if let Ok((program, _)) = Program::from_ir(ir) {
+                    return Ok(program);
+                }
             }
         }
     }
 
-    replace_in_ast(ast, replace);
-}
+    let mut program = Program::parse_with(source, parse)?;
+    if let Some(config) = opt {
+        program.optimize(config);
+    }
 
-/// Replaces 'zeroing' instructions with a single token to reduce time spent in loops.
-///
-/// This replaces `[-]` and `[+]` (and all variants of these which have an odd number of inner symbols)
-/// with a single token of `TokenType::Set` and value `0`.
-///
-/// This pass must be run after Collapse Duplicated.
-fn pass_zero_cell(ast: &mut Ast) {
-    let mut replace = ReplaceVec::new();
-    let mut progress = 0;
-    for (i, node) in ast.iter().enumerate() {
-        if progress == 1 && (node.tk == TokenType::Sub || node.tk == TokenType::Add) && node.value % 2 == 1 {
-            progress += 1;
-        } else if progress == 2 && node.tk == TokenType::LoopEnd {
-            replace.push((i - 2, i + 1, Token::new(
-                TokenType::Set, 0
-            )));
-            progress = 0;
-        } else if node.tk == TokenType::LoopStart {
-            progress = 1;
-        } else {
-            progress = 0;
+    if let (Some(dir), Some(path)) = (cache_dir, &path) {
+        let mut entry = (key.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(key.as_bytes());
+        entry.extend_from_slice(&program.to_ir(cell_width));
+        if let Err(x) = fs::create_dir_all(dir).and_then(|_| fs::write(path, entry)) {
+            eprintln!("Warning: {}", Error::Io("Could not write to the compile cache", x));
         }
     }
 
-    replace_in_ast(ast, replace);
+    Ok(program)
 }
 
-/// Replaces idiomatic moves of the form `[->+<]` with a `TokenType::Move` tokens, followed by a
-/// single `TokenType::Set` of value 0.
-/// This also accepts any number of left/right tokens, e.g. `[->>>+<<<]` can also be optimized.
-/// Left/right tokens can also be put in the opposite order, e.g. `[-<<<+>>>]`.
-/// It can also optimize multiple moves, such as `[->>+>+>>>+<<<<<<]`.
-///
-/// TODO: optimize multiplying moves, e.g. `[->>++<<]`.
-///
-/// Note that a 'move' adds the value of the src cell to the destination - it doesn't replace it.
-/// The src cell has its value set to 0 afterwards.
-///
-/// This pass must be run after Collapse Duplicated.
-fn pass_move_value(ast: &mut Ast) {
-    let mut moves: Vec<i32> = Vec::new();
-    let mut displacement = 0;
-    let mut replace = ReplaceVec::new();
-    let mut progress = 0;
-
-    for (i, node) in ast.iter().enumerate() {
-        if progress == 1 && node.tk == TokenType::Sub && node.value == 1 {
-            progress += 1;
-            moves.clear();
-            displacement = 0;
-        } else if progress == 2 && (node.tk == TokenType::Left || node.tk == TokenType::Right) {
-            if node.tk == TokenType::Left {
-                displacement -= node.value;
-            } else {
-                displacement += node.value;
-            }
-            progress += 1;
-        } else if progress == 3 && node.tk == TokenType::Add && node.value == 1 && displacement != 0 && !moves.contains(&displacement) {
-            moves.push(displacement);
-            progress = 2;
-        } else if progress == 3 && node.tk == TokenType::LoopEnd && displacement == 0 {
-            for (j, m) in moves.iter().enumerate() {
-                let start;
-                if j == 0 {
-                    start = i - moves.len() * 2 - 3;
-                } else {
-                    start = i - (moves.len() - j) * 2;
-                }
-
-                let end;
-                if j == 0 {
-                    end = start + 5;
-                } else {
-                    end = start + 2;
-                }
-
-                replace.push((start, end, Token::new(
-                    TokenType::Move, *m
-                )));
-            }
+/// Writes the program in the format chosen by `--emit`, to the `--output` file or stdout.
+fn emit(program: &Program, format: Emit, cell_width: CellWidth, output: &Option<String>) -> Result<(), Error> {
+    let bytes = match format {
+        Emit::Ir => program.to_ir(cell_width),
+    };
 
-            replace.push((i, i + 1, Token::new(
-                TokenType::Set, 0
-            )));
+    let res = match output {
+        Some(filename) => fs::write(filename, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
 
-            moves.clear();
-            progress = 0;
-        } else if node.tk == TokenType::LoopStart {
-            progress = 1;
-        } else {
-            progress = 0;
-        }
+    match res {
+        Ok(_) => Ok(()),
+        Err(x) => Err(Error::Io("Could not write compiled program", x)),
     }
-
-    replace_in_ast(ast, replace);
 }
 
-/// Runs optimizer passes on the AST.
-fn optimize(ast: &mut Ast) {
-    pass_collapse_duplicated(ast);
-    pass_zero_cell(ast);
-    pass_move_value(ast);
+/// A way of running the program which watches it as it goes, instead of just running it.
+enum Mode<'a> {
+    Debug,
+    Trace(&'a TraceOptions),
+    Profile(&'a ProfileOptions),
 }
 
-/// Caches loop jump endpoints to reduce time spent searching during
-/// execution.
-fn link_loops(ast: &mut Ast) -> Result<(), &'static str> {
-    let mut loop_stack: Vec<usize> = Vec::new();
-
-    for i in 0..ast.len() {
-        match ast[i].tk {
-            TokenType::LoopStart => {
-                loop_stack.push(i);
-            },
-            TokenType::LoopEnd => {
-                let jmp = match loop_stack.pop() {
-                    Some(x) => x,
-                    None => return Err("Unmatched ]")
-                };
-
-                ast[i].value = jmp as i32;
-                ast[jmp].value = i as i32;
-            }
-            _ => {},
-        }
-    };
-
-    if loop_stack.len() > 0 {
-        Err("Unmatched [")
-    } else {
-        Ok(())
+impl Mode<'_> {
+    /// The error given when the mode is used with `--cell-size auto`.
+    fn auto_error(&self) -> Error {
+        Error::Config(match self {
+            Mode::Debug => "Cell size auto isn't supported by the debugger",
+            Mode::Trace(_) => "Cell size auto isn't supported with --trace",
+            Mode::Profile(_) => "Cell size auto isn't supported with --profile",
+        })
     }
 }
 
-/// Runs the AST.
-fn execute<T>(ast: &Ast, tape_size: usize) -> Result<(), &'static str>
-where T: CellSize + Clone + Copy
+/// Runs the program under `--debug`, `--trace` or `--profile`, dumping the tape as it was left.
+fn run_mode<T, I>(mode: &Mode, program: &Program, source: &str, config: &RunConfig, tape_options: &TapeOptions, io: &mut I) -> Result<(), Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
 {
-    if tape_size < 1 {
-        return Err("Tape size must be greater than 0");
-    }
+    let tape = load_tape::<T>(config, tape_options)?;
+    let tape = match mode {
+        Mode::Debug => debug::run(program, source, config, tape, io)?,
+        Mode::Trace(options) => trace::run(program, source, config, tape, io, options)?,
+        Mode::Profile(options) => profile::run(program, source, config, tape, io, options)?,
+    };
 
-    let mut cells: Vec<T> = T::get_zeroes(1000).collect();
-    let mut data_pointer = 0;
-    let mut stdout = io::stdout();
-    let mut stdin = io::stdin();
-    let mut instruction_pointer = 0;
-
-    loop {
-        let token = &ast[instruction_pointer];
-        match token.tk {
-            TokenType::Add => {
-                cells[data_pointer].add_to_cell(T::from_tk_value(token.value));
-            },
-            TokenType::Sub => {
-                cells[data_pointer].sub_from_cell(T::from_tk_value(token.value));
-            },
-            TokenType::Left => {
-                if data_pointer < token.value as usize {
-                    return Err("Data pointer moved out of bounds (too far left)")
-                }
-                data_pointer -= token.value as usize;
-            },
-            TokenType::Right => {
-                let new_pos = data_pointer + token.value as usize;
-                if new_pos >= tape_size {
-                    return Err("Data pointer moved out of bounds (too far right)")
-                } else if new_pos > cells.len() {
-                    // Allocate more space for the tape, we need it
-                    cells.extend(
-                        T::get_zeroes(new_pos - cells.len() + 1000)
-                    );
-                }
+    dump_tape(&tape, tape_options)
+}
 
-                data_pointer += token.value as usize;
-            },
-            TokenType::LoopStart => {
-                if cells[data_pointer].is_zero() {
-                    instruction_pointer = token.value as usize;
-                }
-            },
-            TokenType::LoopEnd => {
-                if cells[data_pointer].is_nonzero() {
-                    instruction_pointer = token.value as usize;
-                }
-            },
-            TokenType::In => {
-                let mut buf = [0];
-                match stdin.read_exact(&mut buf) {
-                    Ok(_) => {
-                        cells[data_pointer] = T::from_stdin(buf[0]);
-                    },
-                    Err(x) => {
-                        if x.kind() == io::ErrorKind::UnexpectedEof {
-                            // Treat EOF as 0
-                            cells[data_pointer] = T::from_tk_value(0);
-                        } else {
-                            return Err("Could not read from stdin")
-                        }
-                    }
-                }
-            },
-            TokenType::Out => {
-                let buf = [cells[data_pointer].to_stdout()];
-                match stdout.write(&buf) {
-                    Ok(_) => {},
-                    Err(_) => return Err("Could not write to stdout")
-                }
+/// Sets up the tape, runs the program, and dumps the final tape if requested.
+fn run<T, I>(program: &Program, config: &RunConfig, tape_options: &TapeOptions, io: &mut I) -> Result<(), Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let tape = load_tape::<T>(config, tape_options)?;
+    let halt = program.run_with_tape(config, tape, io)?;
 
-                match stdout.flush() {
-                    Ok(_) => {},
-                    Err(_) => return Err("Could not flush stdout")
-                }
-            },
-            TokenType::Set => {
-                cells[data_pointer] = T::from_tk_value(token.value);
-            },
-            TokenType::Move => {
-                if cells[data_pointer].is_nonzero() {
-                    let dest = data_pointer as i32 + token.value;
-                    if dest < 0 {
-                        return Err("Data pointer moved out of bounds (too far left)")
-                    }
-
-                    let dest = dest as usize;
-                    if dest >= tape_size {
-                        return Err("Data pointer moved out of bounds (too far right)")
-                    } else if dest > cells.len() {
-                        // Allocate more space for the tape, we need it
-                        // TODO this is duplicated code, refactor this in future
-                        cells.extend(
-                            T::get_zeroes(dest - cells.len() + 1000)
-                        );
-                    }
-
-                    let val = cells[data_pointer];
-                    cells[dest].add_to_cell(val);
-                }
-            },
-            TokenType::End => return Ok(()),
-            _ => {},
-        }
+    finish(halt, tape_options, io)
+}
 
-        instruction_pointer += 1;
+/// Runs the program with `--cell-size auto`, and reports the chosen width on stderr.
+fn run_auto<I>(program: &Program, config: &RunConfig, tape_options: &TapeOptions, io: &mut I) -> Result<(), Error>
+where I: BfIo<u64> + BfIo<u8>
+{
+    let tape = load_tape::<u64>(config, tape_options)?;
+
+    match program.run_auto(config, tape, io)? {
+        AutoWidth::Wide(halt) => {
+            eprintln!("Cell size: 64 bits (a cell went above 255)");
+            finish::<u64, _>(halt, tape_options, io)
+        },
+        AutoWidth::Wrapped(halt) => {
+            eprintln!("Cell size: 8 bits (a cell went below zero)");
+            finish::<u8, _>(halt, tape_options, io)
+        },
+        AutoWidth::InRange(halt) => {
+            eprintln!("Cell size: 8 bits (all cells stayed within 0..255)");
+            finish::<u8, _>(halt, tape_options, io)
+        },
     }
 }
 
-fn main() -> Result<(), &'static str> {
+/// Parses the command line and runs the program.
+fn try_main() -> Result<(), Error> {
+    match env::args().nth(1).as_deref() {
+        Some("batch") => return batch::main(),
+        Some("check") => return check::main(),
+        _ => {},
+    }
+
     let mut filename = String::new();
     let mut raw = String::new();
     let mut do_optimize = true;
-    let mut cell_size: u8 = 8;
-    let mut tape_size: usize = 30000;
+    let mut parse_config = ParseConfig::default();
+    let mut cell_size: Option<CellWidth> = None;
+    let mut config = RunConfig::default();
+    let mut debug = false;
     let mut dump = false;
+    let mut dump_view = DumpView::Tokens;
+    let mut ast_format = AstFormat::Text;
+    let mut emit_format: Option<Emit> = None;
+    let mut cache: Option<String> = None;
+    let mut color = ColorMode::Auto;
+    let mut tape_options = TapeOptions {
+        init: None,
+        init_format: InitFormat::Raw,
+        dump: None,
+        dump_format: DumpFormat::Hex,
+    };
+    let mut trace_options = TraceOptions {
+        file: None,
+        format: TraceFormat::Text,
+        range: None,
+        in_loop: None,
+    };
+    let mut profile_options = ProfileOptions {
+        report: false,
+        folded: None,
+    };
+    let mut io_options = IoOptions {
+        input: None,
+        input_string: None,
+        input_hex: None,
+        output: None,
+        mode: IoMode::Bytes,
+        flush: Flush::Auto,
+        max_output: None,
+        newline: None,
+        interactive: false,
+    };
 
     {  // this block limits scope of borrows by ap.refer() method
         let mut ap = ArgumentParser::new();
-        ap.set_description("Run brainfuck code.");
+        ap.set_description("Run brainfuck code. Use  rustfuck batch DIR  to run every program in a directory, and  rustfuck check FILE  to look for mistakes without running it. Each has its own --help.");
         ap.refer(&mut filename)
-            .add_argument("filename", Store, "File containing brainfuck code");
+            .add_argument("filename", Store, "File containing brainfuck code or a compiled .bfc program, or - to read it from stdin");
         ap.refer(&mut raw)
             .add_option(&["-r", "--raw"], Store, "Raw brainfuck code to run");
         ap.refer(&mut do_optimize)
             .add_option(&["--no-optimize"], StoreFalse, "Don't optimize code");
+        ap.refer(&mut parse_config.strict)
+            .add_option(&["--strict"], StoreTrue, "Reject characters other than commands and whitespace, unless they're in a comment enabled by --hash-comments or --comment-block.");
+        ap.refer(&mut parse_config.hash_comments)
+            .add_option(&["--hash-comments"], StoreTrue, "Treat # and the rest of its line as a comment.");
+        ap.refer(&mut parse_config.debug_char)
+            .add_option(&["--debug-char"], StoreOption, "Character which prints the data pointer and the cells around it to stderr when run, usually #. Can't be used with # as --hash-comments.");
+        ap.refer(&mut config.debug_window)
+            .add_option(&["--debug-window"], Store, "How many cells either side of the current one are printed by --debug-char. Default 8.");
+        ap.refer(&mut parse_config.comment_block)
+            .add_option(&["--comment-block"], StoreTrue, "Treat a loop at the very start of the program as a comment, skipping everything in it.");
         ap.refer(&mut cell_size)
-            .add_option(&["-s", "--cell-size"], Store, "Size of each cell in bits. Accepted values: 8, 16, 32, 64. Default 8.");
-        ap.refer(&mut tape_size)
+            .add_option(&["-s", "--cell-size"], StoreOption, "Size of each cell in bits. Accepted values: 8, 16, 32, 64, auto. Default 8, or the size recorded in a .bfc program. With auto, the program runs with 64-bit cells, switching to 8 bits if a cell goes below zero before any goes above 255, and the chosen size is printed to stderr. A loop which relies on a cell above 255 wrapping back round to zero stops the program with an error.");
+        ap.refer(&mut config.tape_size)
             .add_option(&["-t", "--tape-size"], Store, "Size of the data tape. Default 30000.");
+        ap.refer(&mut config.eof)
+            .add_option(&["--eof"], Store, "Value stored by , at the end of input. Accepted values: zero, minus-one (all bits set), unchanged. Default zero.");
+        ap.refer(&mut io_options.mode)
+            .add_option(&["--io"], Store, "How cells are read and written. Accepted values: bytes, utf8 (one Unicode character per cell), decimal (whitespace-separated numbers). Default bytes.");
+        ap.refer(&mut io_options.flush)
+            .add_option(&["--flush"], Store, "When output is flushed. Accepted values: byte, line, exit, auto (before waiting for input). Default auto.");
+        ap.refer(&mut io_options.input)
+            .add_option(&["--input"], StoreOption, "File to read input from instead of stdin.");
+        ap.refer(&mut io_options.input_string)
+            .add_option(&["--input-string"], StoreOption, "String to use as input instead of stdin.");
+        ap.refer(&mut io_options.input_hex)
+            .add_option(&["--input-hex"], StoreOption, "Hex-encoded bytes to use as input instead of stdin.");
+        ap.refer(&mut io_options.output)
+            .add_option(&["--output"], StoreOption, "File to write output to instead of stdout.");
+        ap.refer(&mut io_options.max_output)
+            .add_option(&["--max-output"], StoreOption, "Stop with an error once the program has written this many bytes.");
+        ap.refer(&mut io_options.newline)
+            .add_option(&["--newline"], StoreOption, "Translate line endings in the input. Accepted values: lf (CR and CRLF become LF), cr (LF and CRLF become CR), crlf-to-lf. Default no translation.");
+        ap.refer(&mut io_options.interactive)
+            .add_option(&["--interactive"], StoreTrue, "Print a prompt marker to stderr whenever the program waits for a new line of input.");
+        ap.refer(&mut color)
+            .add_option(&["--color"], Store, "Whether error messages about the code are colored. Accepted values: auto (when printing to a terminal, unless NO_COLOR is set), always, never. Default auto.");
+        ap.refer(&mut debug)
+            .add_option(&["--debug"], StoreTrue, "Step through the program at an interactive prompt, with breakpoints. Commands are read from stdin, so a program which reads input needs --input, --input-string or --input-hex. Type help at the prompt for a list of commands.");
+        ap.refer(&mut trace_options.file)
+            .add_option(&["--trace"], StoreOption, "File to log every token run to, with the instruction pointer, data pointer, current cell value and source position. Nothing is written to the program's output.");
+        ap.refer(&mut trace_options.format)
+            .add_option(&["--trace-format"], Store, "Format of --trace. Accepted values: text (one tab-separated line per token), binary (compact, for long runs). Default text.");
+        ap.refer(&mut trace_options.range)
+            .add_option(&["--trace-range"], StoreOption, "Only trace tokens which start in the source range START-END, where each is LINE or LINE:COL, or on a single LINE.");
+        ap.refer(&mut trace_options.in_loop)
+            .add_option(&["--trace-loop"], StoreOption, "Only trace tokens in the loop starting at LINE:COL or #INDEX, including its brackets.");
+        ap.refer(&mut profile_options.report)
+            .add_option(&["--profile"], StoreTrue, "Count how often each token runs, and print the hottest loops to stderr at exit, with their source positions, iterations and whether they're innermost loops.");
+        ap.refer(&mut profile_options.folded)
+            .add_option(&["--profile-folded"], StoreOption, "File to write the profile to as folded stacks keyed by loop nesting, for flamegraph tools.");
         ap.refer(&mut dump)
             .add_option(&["--dump"], StoreTrue, "Dump the AST and exit without executing the code.");
+        ap.refer(&mut dump_view)
+            .add_option(&["--dump-view"], Store, "How --dump lays out the AST. Accepted values: tokens, annotated (one token per line with the source line, column and text it was made from), side-by-side (each source line next to its tokens). Default tokens.");
+        ap.refer(&mut ast_format)
+            .add_option(&["--dump-format"], Store, "Format of --dump. Accepted values: text (laid out by --dump-view), json (tokens with loop targets and source positions), dot (a Graphviz control-flow graph), html (the source code marked with the tokens made from it). Default text.");
+        ap.refer(&mut emit_format)
+            .add_option(&["--emit"], StoreOption, "Write the optimized program to --output or stdout and exit without executing it. Accepted values: ir (a compiled .bfc program, which can be run directly).");
+        ap.refer(&mut cache)
+            .add_option(&["--cache"], StoreOption, "Directory to cache compiled programs in, so unchanged source code isn't parsed and optimized again.");
+        ap.refer(&mut tape_options.init)
+            .add_option(&["--tape-init"], StoreOption, "File to load the initial tape contents from.");
+        ap.refer(&mut tape_options.init_format)
+            .add_option(&["--tape-init-format"], Store, "Format of the tape init file. Accepted values: raw (one byte per cell), numbers (whitespace-separated). Default raw.");
+        ap.refer(&mut tape_options.dump)
+            .add_option(&["--dump-tape"], StoreOption, "File to write the final tape and data pointer to after execution, including when --max-output stops it.");
+        ap.refer(&mut tape_options.dump_format)
+            .add_option(&["--dump-tape-format"], Store, "Format of the tape dump. Accepted values: hex, decimal, json. Default hex.");
         ap.parse_args_or_exit();
     }
 
-    if filename != "" {
-        raw = match fs::read_to_string(filename) {
+    // Checked before anything is opened, so a rejected command line doesn't truncate --output
+    let profiling = profile_options.report || profile_options.folded.is_some();
+    let mode = match (debug, trace_options.file.is_some(), profiling) {
+        (false, false, false) => None,
+        (true, false, false) => Some(Mode::Debug),
+        (false, true, false) => Some(Mode::Trace(&trace_options)),
+        (false, false, true) => Some(Mode::Profile(&profile_options)),
+        _ => return Err(Error::Config("Only one of --debug, --trace and --profile can be used at once")),
+    };
+
+    let code = if filename == "-" {
+        let mut code = Vec::new();
+        if let Err(x) = io::stdin().read_to_end(&mut code) {
+            return Err(Error::Io("Could not read code from stdin", x));
+        }
+        code
+    } else if !filename.is_empty() {
+        match fs::read(&filename) {
             Ok(x) => x,
-            Err(_) => return Err("Could not open file"),
+            Err(x) => return Err(Error::Io("Could not open file", x)),
         }
-    } else if raw == "" {
-        return Err("Please provide a filename. Use flag  --help  for usage help.")
-    }
-
-    let mut ast = match parse(&raw) {
-        Ok(ast) => ast,
-        Err(err) => return Err(err),
+    } else if raw.is_empty() {
+        return Err(Error::Config("Please provide a filename. Use flag  --help  for usage help."))
+    } else {
+        raw.into_bytes()
     };
 
-    if do_optimize {
-        optimize(&mut ast);
+    // Tokens loaded from a .bfc program don't know where they came from, so dumps, the debugger,
+    // traces and profiles skip the cache
+    if dump || mode.is_some() {
+        cache = None;
     }
 
-    match link_loops(&mut ast) {
-        Ok(_) => {},
-        Err(err) => return Err(err),
+    let is_ir = ir::is_ir(&code);
+    let source = if is_ir { String::new() } else { String::from_utf8_lossy(&code).into_owned() };
+    let program = if is_ir {
+        let (program, compiled_width) = Program::from_ir(&code)?;
+        cell_size = cell_size.or(Some(compiled_width));
+        program
+    } else {
+        let opt = OptConfig::default();
+        let opt = if do_optimize { Some(&opt) } else { None };
+        match compile(&source, &parse_config, opt, &cache, cell_size.unwrap_or(CellWidth::Bits(8))) {
+            Ok(x) => x,
+            Err(Error::Syntax(diagnostics)) => {
+                let name = match filename.as_str() {
+                    "" => "<raw>",
+                    "-" => "<stdin>",
+                    x => x,
+                };
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", diagnostic.render(&source, name, color.use_color()));
+                }
+                return Err(Error::Syntax(diagnostics));
+            },
+            Err(x) => return Err(x),
+        }
     };
-
+    let cell_size = cell_size.unwrap_or(CellWidth::Bits(8));
 
     if dump {
-        println!("{}", ast.dump());
+        println!("{}", program.dump_as(ast_format, dump_view, &source));
         return Ok(());
     }
 
+    if let Some(format) = emit_format {
+        return emit(&program, format, cell_size, &io_options.output);
+    }
+
+    if let Some(Mode::Debug) = mode {
+        debug::check_stdin(&program, filename == "-", io_options.reads_stdin())?;
+    }
+
+    let mut io = io_options.open()?;
+
+    if let Some(mode) = mode {
+        return match cell_size {
+            CellWidth::Bits(8) => run_mode::<u8, _>(&mode, &program, &source, &config, &tape_options, &mut io),
+            CellWidth::Bits(16) => run_mode::<u16, _>(&mode, &program, &source, &config, &tape_options, &mut io),
+            CellWidth::Bits(32) => run_mode::<u32, _>(&mode, &program, &source, &config, &tape_options, &mut io),
+            CellWidth::Bits(64) => run_mode::<u64, _>(&mode, &program, &source, &config, &tape_options, &mut io),
+            CellWidth::Auto => Err(mode.auto_error()),
+            CellWidth::Bits(bits) => Err(Error::UnsupportedCellSize(bits)),
+        };
+    }
+
     match cell_size {
-        8 => execute::<u8>(&ast, tape_size),
-        16 => execute::<u16>(&ast, tape_size),
-        32 => execute::<u32>(&ast, tape_size),
-        64 => execute::<u64>(&ast, tape_size),
-        _ => Err("Unsupported cell size")
+        CellWidth::Bits(8) => run::<u8, _>(&program, &config, &tape_options, &mut io),
+        CellWidth::Bits(16) => run::<u16, _>(&program, &config, &tape_options, &mut io),
+        CellWidth::Bits(32) => run::<u32, _>(&program, &config, &tape_options, &mut io),
+        CellWidth::Bits(64) => run::<u64, _>(&program, &config, &tape_options, &mut io),
+        CellWidth::Auto => run_auto(&program, &config, &tape_options, &mut io),
+        CellWidth::Bits(bits) => Err(Error::UnsupportedCellSize(bits)),
     }
+}
 
+fn main() {
+    if let Err(x) = try_main() {
+        match x {
+            // Already printed with snippets of the source code
+            Error::Syntax(_) | Error::Lint(_) => {},
+            _ => eprintln!("Error: {}", x),
+        }
+        process::exit(x.exit_code());
+    }
 }
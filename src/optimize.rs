@@ -0,0 +1,256 @@
+use crate::ast::{Ast, Token, TokenType};
+
+/// Chooses which optimizer passes are run.
+#[derive(Debug, Clone)]
+pub struct OptConfig {
+    /// Collapse runs of `+`, `-`, `>`, `<` and `.` into single tokens.
+    pub collapse_duplicated: bool,
+    /// Replace `[-]` and similar loops with a single `Set` token.
+    pub zero_cell: bool,
+    /// Replace loops such as `[->+<]` with `Move` tokens.
+    pub move_value: bool,
+}
+
+impl OptConfig {
+    /// Runs no optimizer passes at all.
+    pub fn none() -> OptConfig {
+        OptConfig {
+            collapse_duplicated: false,
+            zero_cell: false,
+            move_value: false,
+        }
+    }
+}
+
+impl Default for OptConfig {
+    /// Runs every optimizer pass.
+    fn default() -> OptConfig {
+        OptConfig {
+            collapse_duplicated: true,
+            zero_cell: true,
+            move_value: true,
+        }
+    }
+}
+
+/// A vector of replacements to be made with form:
+/// `(begin, end, replacement)`
+/// where `begin` is included and `end` is excluded.
+type ReplaceVec = Vec::<(usize, usize, Token)>;
+
+/// Given a sorted vector of replacements to be made, replace the given
+/// ranges of tokens with a single other token in the AST.
+///
+/// Each replacement token is given a span covering all the tokens it replaces.
+fn replace_in_ast(ast: &mut Ast, mut replacements: ReplaceVec) {
+    replacements.reverse();
+    for (start, end, mut token) in replacements {
+        token.span = ast.drain(start..end)
+            .map(|t| t.span)
+            .reduce(|a, b| a.merge(&b))
+            .unwrap_or_default();
+        ast.insert(start, token);
+    }
+}
+
+/// Collapses duplicated tokens into a single token.
+///
+/// e.g. `------`, which is represented as six `TokenType::Sub` with value `1`,
+/// is replaced by a single `TokenType::Sub` with value `6`. This applies to `-`, `+`, `>`, `<`, and `.`,
+/// where a collapsed `.` writes the current cell that many times.
+fn pass_collapse_duplicated(ast: &mut Ast) {
+    let mut start: usize = 0;
+    let mut count: usize = 0;
+    let mut current = TokenType::Invalid;
+    let mut replace = ReplaceVec::new();
+    for (i, node) in ast.iter().enumerate() {
+        if node.tk == current {
+            count += 1;
+        } else {
+            if count > 1 {
+                replace.push((start, i, Token::new(current, count as i32)));
+            }
+
+            if node.tk == TokenType::Add
+            || node.tk == TokenType::Sub
+            || node.tk == TokenType::Left
+            || node.tk == TokenType::Right
+            || node.tk == TokenType::Out {
+                start = i;
+                count = 1;
+                current = node.tk;
+            } else {
+                count = 0;
+                current = TokenType::Invalid;
+            }
+        }
+    }
+
+    replace_in_ast(ast, replace);
+}
+
+/// Replaces 'zeroing' instructions with a single token to reduce time spent in loops.
+///
+/// This replaces `[-]` and `[+]` (and all variants of these which have an odd number of inner symbols)
+/// with a single token of `TokenType::Set` and value `0`.
+///
+/// This pass must be run after Collapse Duplicated.
+fn pass_zero_cell(ast: &mut Ast) {
+    let mut replace = ReplaceVec::new();
+    let mut progress = 0;
+    for (i, node) in ast.iter().enumerate() {
+        if progress == 1 && (node.tk == TokenType::Sub || node.tk == TokenType::Add) && node.value % 2 == 1 {
+            progress += 1;
+        } else if progress == 2 && node.tk == TokenType::LoopEnd {
+            replace.push((i - 2, i + 1, Token::new(
+                TokenType::Set, 0
+            )));
+            progress = 0;
+        } else if node.tk == TokenType::LoopStart {
+            progress = 1;
+        } else {
+            progress = 0;
+        }
+    }
+
+    replace_in_ast(ast, replace);
+}
+
+/// Replaces idiomatic moves of the form `[->+<]` with a `TokenType::Move` tokens, followed by a
+/// single `TokenType::Set` of value 0.
+/// This also accepts any number of left/right tokens, e.g. `[->>>+<<<]` can also be optimized.
+/// Left/right tokens can also be put in the opposite order, e.g. `[-<<<+>>>]`.
+/// It can also optimize multiple moves, such as `[->>+>+>>>+<<<<<<]`.
+///
+/// TODO: optimize multiplying moves, e.g. `[->>++<<]`.
+///
+/// Note that a 'move' adds the value of the src cell to the destination - it doesn't replace it.
+/// The src cell has its value set to 0 afterwards.
+///
+/// This pass must be run after Collapse Duplicated.
+fn pass_move_value(ast: &mut Ast) {
+    let mut moves: Vec<i32> = Vec::new();
+    let mut displacement = 0;
+    let mut replace = ReplaceVec::new();
+    let mut progress = 0;
+
+    for (i, node) in ast.iter().enumerate() {
+        if progress == 1 && node.tk == TokenType::Sub && node.value == 1 {
+            progress += 1;
+            moves.clear();
+            displacement = 0;
+        } else if progress == 2 && (node.tk == TokenType::Left || node.tk == TokenType::Right) {
+            if node.tk == TokenType::Left {
+                displacement -= node.value;
+            } else {
+                displacement += node.value;
+            }
+            progress += 1;
+        } else if progress == 3 && node.tk == TokenType::Add && node.value == 1 && displacement != 0 && !moves.contains(&displacement) {
+            moves.push(displacement);
+            progress = 2;
+        } else if progress == 3 && node.tk == TokenType::LoopEnd && displacement == 0 {
+            for (j, m) in moves.iter().enumerate() {
+                let start = if j == 0 {
+                    i - moves.len() * 2 - 3
+                } else {
+                    i - (moves.len() - j) * 2
+                };
+
+                let end = if j == 0 {
+                    start + 5
+                } else {
+                    start + 2
+                };
+
+                replace.push((start, end, Token::new(
+                    TokenType::Move, *m
+                )));
+            }
+
+            replace.push((i, i + 1, Token::new(
+                TokenType::Set, 0
+            )));
+
+            moves.clear();
+            progress = 0;
+        } else if node.tk == TokenType::LoopStart {
+            progress = 1;
+        } else {
+            progress = 0;
+        }
+    }
+
+    replace_in_ast(ast, replace);
+}
+
+/// Runs the optimizer passes enabled in `config` on the AST.
+///
+/// `TokenType::Debug` is a barrier: no pass matches it, so it's never removed, and runs and loops
+/// on either side of it are optimized separately. `+#+` stays as three tokens, and `[->#+<]`
+/// isn't turned into a move.
+pub fn optimize(ast: &mut Ast, config: &OptConfig) {
+    if config.collapse_duplicated {
+        pass_collapse_duplicated(ast);
+    }
+    if config.zero_cell {
+        pass_zero_cell(ast);
+    }
+    if config.move_value {
+        pass_move_value(ast);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Dumpable, Span};
+    use crate::execute::RunConfig;
+    use crate::parse::{self, ParseConfig};
+    use crate::testing::run;
+
+    fn optimized(source: &str) -> Ast {
+        let mut ast = parse::parse(source, &ParseConfig::default()).unwrap();
+        optimize(&mut ast, &OptConfig::default());
+        ast
+    }
+
+    #[test]
+    fn fuses_runs_of_output() {
+        let ast = optimized("+...>++..");
+        assert_eq!(ast[1].tk, TokenType::Out);
+        assert_eq!(ast[1].value, 3);
+        assert_eq!(ast.dump(), "+ .3 > +2 .2 : \n");
+
+        let (out, _) = run::<u8>("+...>++..", &RunConfig::default(), &[]).unwrap();
+        assert_eq!(out, [1, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn merged_tokens_cover_what_they_replace() {
+        let ast = optimized("+++\n[-]>[->+<]");
+
+        assert_eq!(ast[0].tk, TokenType::Add);
+        assert_eq!(ast[0].span, Span { start: 0, end: 3, line: 1, col: 1 });
+        assert_eq!(ast[1].tk, TokenType::Set);
+        assert_eq!(ast[1].span, Span { start: 4, end: 7, line: 2, col: 1 });
+        assert_eq!(ast[3].tk, TokenType::Move);
+        assert_eq!(ast[3].span, Span { start: 8, end: 13, line: 2, col: 5 });
+        assert_eq!(ast[4].tk, TokenType::Set);
+        assert_eq!(ast[4].span, Span { start: 13, end: 14, line: 2, col: 10 });
+    }
+
+    #[test]
+    fn does_not_optimize_across_the_debug_character() {
+        let config = ParseConfig { debug_char: Some('#'), ..ParseConfig::default() };
+        let optimized = |source| {
+            let mut ast = parse::parse(source, &config).unwrap();
+            optimize(&mut ast, &OptConfig::default());
+            ast.iter().map(|t| t.tk).collect::<Vec<_>>()
+        };
+
+        assert_eq!(optimized("+#+"), [TokenType::Add, TokenType::Debug, TokenType::Add, TokenType::End]);
+        assert!(!optimized("[->#+<]").contains(&TokenType::Move));
+        assert!(!optimized("[#-]").contains(&TokenType::Set));
+    }
+}
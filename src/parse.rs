@@ -0,0 +1,329 @@
+use crate::ast::{Ast, Span, Token, TokenType};
+use crate::diagnostic::Diagnostic;
+use crate::error::Error;
+
+/// Settings for how source code is read. By default, every character other than the eight
+/// commands is ignored.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct ParseConfig {
+    /// Treat `#` and the rest of its line as a comment.
+    pub hash_comments: bool,
+    /// Treat a loop at the very start of the program, which can never run, as a comment. Its
+    /// contents are skipped, but its brackets still have to match up.
+    pub comment_block: bool,
+    /// Reject characters which aren't commands, whitespace or part of a comment.
+    pub strict: bool,
+    /// A character which prints the tape around the data pointer, usually `#`.
+    pub debug_char: Option<char>,
+}
+
+/// Parses raw text into an intermediate representation, recording where each token came from.
+///
+/// Fails with a diagnostic for every run of stray characters in strict mode, and for a leading
+/// comment block which is never closed. Fails with `Error::Config` if the debug character is
+/// already a command, whitespace, or starts a comment.
+pub fn parse(raw: &str, config: &ParseConfig) -> Result<Ast, Error> {
+    match config.debug_char {
+        Some('+' | '-' | '>' | '<' | '.' | ',' | '[' | ']') => {
+            return Err(Error::Config("The debug character can't be a brainfuck command"));
+        },
+        Some(c) if c.is_whitespace() => {
+            return Err(Error::Config("The debug character can't be whitespace"));
+        },
+        Some('#') if config.hash_comments => {
+            return Err(Error::Config("The debug character can't be # when # starts a comment"));
+        },
+        _ => {},
+    }
+
+    let mut ast = Ast::new();
+    let mut diagnostics = Vec::new();
+    let mut line = 1;
+    let mut col = 0;
+
+    let mut in_hash_comment = false;
+    // Whether a leading comment block could still start, because only whitespace and `#`
+    // comments have been seen so far
+    let mut leading = config.comment_block;
+    let mut block_depth = 0;
+    let mut block_start = Span::default();
+    // The run of stray characters being collected, reported as one diagnostic
+    let mut stray: Option<Span> = None;
+
+    for (i, c) in raw.char_indices() {
+        if c == '\n' {
+            line += 1;
+            col = 0;
+            in_hash_comment = false;
+        } else {
+            col += 1;
+        }
+        let span = Span { start: i, end: i + c.len_utf8(), line, col };
+
+        let mut is_stray = false;
+        let command = if in_hash_comment || c.is_whitespace() {
+            None
+        } else if block_depth > 0 {
+            match c {
+                '[' => block_depth += 1,
+                ']' => block_depth -= 1,
+                _ => {},
+            }
+            None
+        } else if leading && c == '[' {
+            block_depth = 1;
+            block_start = span;
+            None
+        } else if config.hash_comments && c == '#' {
+            in_hash_comment = true;
+            None
+        } else if config.debug_char == Some(c) {
+            Some((TokenType::Debug, 0))
+        } else {
+            match c {
+                '+' => Some((TokenType::Add, 1)),
+                '-' => Some((TokenType::Sub, 1)),
+                '>' => Some((TokenType::Right, 1)),
+                '<' => Some((TokenType::Left, 1)),
+                '.' => Some((TokenType::Out, 1)),
+                ',' => Some((TokenType::In, 0)),
+                '[' => Some((TokenType::LoopStart, -1)),
+                ']' => Some((TokenType::LoopEnd, -1)),
+                _ => {
+                    is_stray = config.strict;
+                    None
+                },
+            }
+        };
+
+        if !c.is_whitespace() && !in_hash_comment {
+            leading = false;
+        }
+
+        if is_stray {
+            stray = Some(match stray {
+                Some(x) => x.merge(&span),
+                None => span,
+            });
+        } else if let Some(x) = stray.take() {
+            diagnostics.push(stray_error(x));
+        }
+
+        if let Some((tk, value)) = command {
+            ast.push(Token::with_span(tk, value, span));
+        }
+    }
+
+    if let Some(x) = stray {
+        diagnostics.push(stray_error(x));
+    }
+    if block_depth > 0 {
+        diagnostics.push(Diagnostic::error("Unmatched [", block_start, "comment block opened here, never closed"));
+    }
+
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|d| d.span.start);
+        return Err(Error::Syntax(diagnostics));
+    }
+
+    ast.push(
+        Token::with_span(TokenType::End, 0, Span { start: raw.len(), end: raw.len(), line, col: col + 1 })
+    );
+
+    Ok(ast)
+}
+
+/// The diagnostic for characters which strict mode doesn't accept.
+fn stray_error(span: Span) -> Diagnostic {
+    Diagnostic::error("Stray characters", span, "not a command or part of a comment")
+}
+
+/// Caches loop jump endpoints to reduce time spent searching during
+/// execution.
+///
+/// Fails with a diagnostic for every bracket which doesn't match up.
+pub fn link_loops(ast: &mut Ast) -> Result<(), Error> {
+    let mut loop_stack: Vec<usize> = Vec::new();
+    let mut diagnostics = Vec::new();
+    // The most recently closed loop, to point at when a `]` has nothing left to close
+    let mut last_closed: Option<usize> = None;
+
+    for i in 0..ast.len() {
+        match ast[i].tk {
+            TokenType::LoopStart => {
+                loop_stack.push(i);
+            },
+            TokenType::LoopEnd => {
+                let jmp = match loop_stack.pop() {
+                    Some(x) => x,
+                    None => {
+                        let mut diagnostic = Diagnostic::error("Unmatched ]", ast[i].span, "no open loop to close");
+                        if let Some(closed) = last_closed {
+                            diagnostic = diagnostic.with_related(ast[closed].span, "the last open loop was closed here");
+                        }
+                        diagnostics.push(diagnostic);
+                        continue;
+                    },
+                };
+
+                ast[i].value = jmp as i32;
+                ast[jmp].value = i as i32;
+                last_closed = Some(i);
+            }
+            _ => {},
+        }
+    };
+
+    for i in loop_stack {
+        diagnostics.push(Diagnostic::error("Unmatched [", ast[i].span, "loop opened here, never closed"));
+    }
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        diagnostics.sort_by_key(|d| d.span.start);
+        Err(Error::Syntax(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_linked(source: &str) -> Result<Ast, Error> {
+        let mut ast = parse(source, &ParseConfig::default())?;
+        link_loops(&mut ast)?;
+        Ok(ast)
+    }
+
+    /// The messages and positions of the diagnostics `source` fails with.
+    fn errors(result: Result<Ast, Error>) -> Vec<(String, usize, usize)> {
+        match result {
+            Err(Error::Syntax(diagnostics)) => diagnostics.iter()
+                .map(|d| (d.message.clone(), d.span.line, d.span.col))
+                .collect(),
+            x => panic!("expected a syntax error, got {:?}", x.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn records_where_each_token_came_from() {
+        let ast = parse_linked("+ \u{e9}>\n\t[-]").unwrap();
+
+        assert_eq!(ast[0].span, Span { start: 0, end: 1, line: 1, col: 1 });
+        // Columns count characters, offsets count bytes
+        assert_eq!(ast[1].span, Span { start: 4, end: 5, line: 1, col: 4 });
+        assert_eq!(ast[2].span, Span { start: 7, end: 8, line: 2, col: 2 });
+        assert_eq!(ast[4].span, Span { start: 9, end: 10, line: 2, col: 4 });
+        assert_eq!(ast[5].tk, TokenType::End);
+        assert_eq!(ast[5].span, Span { start: 10, end: 10, line: 2, col: 5 });
+    }
+
+    #[test]
+    fn merges_spans() {
+        let a = Span { start: 4, end: 5, line: 2, col: 1 };
+        let b = Span { start: 0, end: 2, line: 1, col: 1 };
+        assert_eq!(a.merge(&b), Span { start: 0, end: 5, line: 1, col: 1 });
+        assert_eq!(b.merge(&a), a.merge(&b));
+    }
+
+    #[test]
+    fn points_at_unmatched_brackets() {
+        assert_eq!(errors(parse_linked("+\n [")), [(String::from("Unmatched ["), 2, 2)]);
+        assert_eq!(errors(parse_linked("[]\n\n  ]")), [(String::from("Unmatched ]"), 3, 3)]);
+    }
+
+    #[test]
+    fn reports_every_unmatched_bracket_in_order() {
+        assert_eq!(errors(parse_linked("]+[\n[]]]\n[")), [
+            (String::from("Unmatched ]"), 1, 1),
+            (String::from("Unmatched ]"), 2, 4),
+            (String::from("Unmatched ["), 3, 1),
+        ]);
+    }
+
+    #[test]
+    fn points_back_at_the_last_closed_loop() {
+        let diagnostics = match parse_linked("[]\n]") {
+            Err(Error::Syntax(x)) => x,
+            x => panic!("expected a syntax error, got {:?}", x.map(|_| ())),
+        };
+
+        assert_eq!(diagnostics[0].label, "no open loop to close");
+        assert_eq!(diagnostics[0].related, [(Span { start: 1, end: 2, line: 1, col: 2 }, String::from("the last open loop was closed here"))]);
+    }
+
+    /// The commands in `source`, as characters.
+    fn commands(source: &str, config: &ParseConfig) -> String {
+        parse(source, config).unwrap()
+            .iter()
+            .filter(|t| t.tk != TokenType::End)
+            .map(|t| match t.tk {
+                TokenType::Add => '+',
+                TokenType::Sub => '-',
+                TokenType::Right => '>',
+                TokenType::Left => '<',
+                TokenType::Out => '.',
+                TokenType::In => ',',
+                TokenType::LoopStart => '[',
+                TokenType::LoopEnd => ']',
+                TokenType::Debug => '#',
+                _ => '?',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_hash_comments_to_the_end_of_the_line() {
+        let config = ParseConfig { hash_comments: true, ..ParseConfig::default() };
+        assert_eq!(commands("+ # add one, then print.\n.", &config), "+.");
+        assert_eq!(commands("+ # add one, then print.\n.", &ParseConfig::default()), "+,..");
+    }
+
+    #[test]
+    fn skips_a_leading_comment_block() {
+        let config = ParseConfig { comment_block: true, ..ParseConfig::default() };
+        assert_eq!(commands("\n [ prints [a] letter, soon. ]\n+.", &config), "+.");
+        // Only a loop before any command is a comment
+        assert_eq!(commands("+[-]", &config), "+[-]");
+
+        let hashes = ParseConfig { hash_comments: true, ..config.clone() };
+        assert_eq!(commands("# header\n[ comment ]+", &hashes), "+");
+
+        assert_eq!(errors(parse("[ [never closed ]", &config)), [(String::from("Unmatched ["), 1, 1)]);
+    }
+
+    #[test]
+    fn rejects_stray_characters_in_strict_mode() {
+        let config = ParseConfig { strict: true, hash_comments: true, ..ParseConfig::default() };
+        assert_eq!(commands("+ \t.\n# comments are fine.", &config), "+.");
+
+        // Each run of stray characters is one error
+        assert_eq!(errors(parse("+abc.\n-x", &config)), [
+            (String::from("Stray characters"), 1, 2),
+            (String::from("Stray characters"), 2, 2),
+        ]);
+        match parse("+abc.", &config) {
+            Err(Error::Syntax(diagnostics)) => assert_eq!((diagnostics[0].span.start, diagnostics[0].span.end), (1, 4)),
+            x => panic!("expected a syntax error, got {:?}", x.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn parses_the_debug_character() {
+        let config = ParseConfig { debug_char: Some('!'), ..ParseConfig::default() };
+        assert_eq!(commands("+!>#", &config), "+#>");
+
+        let hashes = ParseConfig { debug_char: Some('#'), hash_comments: false, ..ParseConfig::default() };
+        assert_eq!(commands("+#", &hashes), "+#");
+    }
+
+    #[test]
+    fn rejects_debug_characters_which_mean_something_else() {
+        let config = |c, hash_comments| ParseConfig { debug_char: Some(c), hash_comments, ..ParseConfig::default() };
+
+        assert!(matches!(parse("+", &config('.', false)), Err(Error::Config("The debug character can't be a brainfuck command"))));
+        assert!(matches!(parse("+", &config('\t', false)), Err(Error::Config("The debug character can't be whitespace"))));
+        assert!(matches!(parse("+", &config('#', true)), Err(Error::Config("The debug character can't be # when # starts a comment"))));
+    }
+}
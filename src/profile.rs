@@ -0,0 +1,291 @@
+//! `--profile`, which counts how often each token runs and reports the hottest loops.
+
+use rustfuck::{Program, RunConfig, CellSize, Error, Tape, Token, TokenType};
+use rustfuck::stream::BfIo;
+
+use std::collections::HashMap;
+use std::fs;
+
+/// How many loops are listed in the report.
+const TOP_LOOPS: usize = 10;
+
+/// How many characters of each loop's source code are shown in the report.
+const LOOP_TEXT_WIDTH: usize = 40;
+
+/// Options for `--profile`.
+pub struct ProfileOptions {
+    /// Print a report of the hottest loops to stderr when the program ends.
+    pub report: bool,
+    /// File to write folded stacks to, for flamegraph tools.
+    pub folded: Option<String>,
+}
+
+/// How often one loop ran.
+struct LoopProfile {
+    /// The index of the loop's `[`.
+    start: usize,
+    /// Tokens run inside the loop, including nested loops and its own brackets.
+    steps: u64,
+    /// How many times the loop body ran.
+    iterations: u64,
+    /// How many times the program reached the loop.
+    reached: u64,
+    /// Whether the loop has no loops inside it. These are the only loops the optimizer could
+    /// replace, so they're the candidates for new optimizations. It's not a sign that any pass
+    /// considered the loop.
+    innermost: bool,
+}
+
+/// A name for the loop starting at `start`, for reports and stacks.
+fn loop_name(tokens: &[Token], start: usize) -> String {
+    let span = tokens[start].span;
+    if span.line > 0 {
+        format!("loop@{}:{}", span.line, span.col)
+    } else {
+        format!("loop#{}", start)
+    }
+}
+
+/// The source code of the loop starting at `start`, on one line and cut short if it's long.
+fn loop_text(source: &str, tokens: &[Token], start: usize) -> String {
+    let end = tokens[start].value as usize;
+    let text = match source.get(tokens[start].span.start..tokens[end].span.end) {
+        Some(x) if tokens[start].span.line > 0 => x.split_whitespace().collect::<Vec<_>>().join(""),
+        _ => return String::new(),
+    };
+
+    if text.chars().count() <= LOOP_TEXT_WIDTH {
+        text
+    } else {
+        let mut clipped: String = text.chars().take(LOOP_TEXT_WIDTH - 3).collect();
+        clipped.push_str("...");
+        clipped
+    }
+}
+
+/// Works out how often each loop ran from the per-token counts.
+fn loops(tokens: &[Token], counts: &[u64]) -> Vec<LoopProfile> {
+    // Running totals of steps and loop starts before each token, so each loop's share is a
+    // subtraction rather than a walk over its body
+    let mut steps_before = Vec::with_capacity(tokens.len() + 1);
+    let mut starts_before = Vec::with_capacity(tokens.len() + 1);
+    steps_before.push(0);
+    starts_before.push(0);
+    for (token, count) in tokens.iter().zip(counts) {
+        steps_before.push(steps_before[steps_before.len() - 1] + count);
+        starts_before.push(starts_before[starts_before.len() - 1] + (token.tk == TokenType::LoopStart) as usize);
+    }
+
+    tokens.iter().enumerate()
+        .filter(|(_, token)| token.tk == TokenType::LoopStart)
+        .map(|(start, token)| {
+            let end = token.value as usize;
+            LoopProfile {
+                start,
+                steps: steps_before[end + 1] - steps_before[start],
+                // Every time round the loop ends at its `]`
+                iterations: counts[end],
+                reached: counts[start],
+                innermost: starts_before[end] == starts_before[start + 1],
+            }
+        })
+        .collect()
+}
+
+/// Formats the hottest loops as an aligned table.
+fn report(source: &str, tokens: &[Token], counts: &[u64]) -> String {
+    let total: u64 = counts.iter().sum();
+    let mut loops = loops(tokens, counts);
+    loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.start.cmp(&b.start)));
+
+    let mut out = format!("Profile: {} steps, {} loops\n", total, loops.len());
+    if loops.is_empty() {
+        return out;
+    }
+    out.push_str("Loops marked innermost have no loops inside them, so an optimizer pass could turn them into a single token.\n");
+
+    out.push_str(&format!(
+        "\n{:>12}  {:>6}  {:>12}  {:>10}  {:16}  LOOP\n",
+        "STEPS", "%", "ITERATIONS", "REACHED", "LOCATION"
+    ));
+    for profile in loops.iter().take(TOP_LOOPS) {
+        let percent = if total > 0 { profile.steps as f64 * 100.0 / total as f64 } else { 0.0 };
+        let note = if profile.innermost { "  (innermost)" } else { "" };
+        out.push_str(&format!(
+            "{:>12}  {:>5.1}%  {:>12}  {:>10}  {:16}  {}{}\n",
+            profile.steps,
+            percent,
+            profile.iterations,
+            profile.reached,
+            loop_name(tokens, profile.start),
+            loop_text(source, tokens, profile.start),
+            note
+        ));
+    }
+    if loops.len() > TOP_LOOPS {
+        out.push_str(&format!("... and {} more loops\n", loops.len() - TOP_LOOPS));
+    }
+
+    out
+}
+
+/// Formats the counts as folded stacks, one line per loop with the steps run directly in it, keyed
+/// by the loops it's nested in, e.g. `program;loop@2:9;loop@3:5 1024`.
+fn folded(tokens: &[Token], counts: &[u64]) -> String {
+    // Each loop's stack is named once, when it opens, and steps are added up against the
+    // innermost open loop
+    let mut names = vec![String::from("program")];
+    let mut steps = vec![0];
+    let mut open: Vec<usize> = vec![0];
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.tk == TokenType::LoopStart {
+            let parent = &names[open[open.len() - 1]];
+            names.push(format!("{};{}", parent, loop_name(tokens, i)));
+            steps.push(0);
+            open.push(names.len() - 1);
+        }
+
+        steps[open[open.len() - 1]] += counts[i];
+
+        if token.tk == TokenType::LoopEnd {
+            open.pop();
+        }
+    }
+
+    let mut stacks: HashMap<String, u64> = HashMap::new();
+    for (name, count) in names.into_iter().zip(steps) {
+        *stacks.entry(name).or_insert(0) += count;
+    }
+
+    let mut stacks: Vec<(String, u64)> = stacks.into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    stacks.sort();
+
+    stacks.iter()
+        .map(|(stack, count)| format!("{} {}\n", stack, count))
+        .collect()
+}
+
+/// Runs the program, counting how many times each token runs, then reports on it as chosen in
+/// `options`. The report is written even if the program fails.
+///
+/// Returns the final tape, or the error the program failed with.
+pub fn run<T, I>(program: &Program, source: &str, config: &RunConfig, tape: Tape<T>, io: &mut I, options: &ProfileOptions) -> Result<Tape<T>, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let tokens = program.tokens();
+    let mut counts = vec![0u64; tokens.len()];
+    let mut interpreter = program.interpreter(config, tape)?;
+
+    let result = rustfuck::drive(&mut interpreter, io, |interpreter| {
+        counts[interpreter.instruction_pointer()] += 1;
+        Ok(true)
+    });
+
+    if options.report {
+        eprint!("{}", report(source, tokens, &counts));
+    }
+    if let Some(filename) = &options.folded {
+        if let Err(x) = fs::write(filename, folded(tokens, &counts)) {
+            return Err(Error::Io("Could not write folded profile", x));
+        }
+    }
+
+    result.map(|_| interpreter.into_tape())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::temp_path;
+    use rustfuck::stream::MemoryIo;
+
+    const NESTED: &str = "++[>++[>+++[>+<-]<-]<-]>>>.";
+
+    /// Runs `source` unoptimized, returning its tokens and how often each ran.
+    fn profile(source: &str) -> (Program, Vec<u64>) {
+        let program = Program::parse(source).unwrap();
+        let mut counts = vec![0; program.tokens().len()];
+        {
+            let mut interpreter = program.interpreter::<u8>(&RunConfig::default(), Tape::default()).unwrap();
+            rustfuck::drive(&mut interpreter, &mut MemoryIo::new(&[]), |interpreter| {
+                counts[interpreter.instruction_pointer()] += 1;
+                Ok(true)
+            }).unwrap();
+        }
+
+        (program, counts)
+    }
+
+    #[test]
+    fn counts_steps_in_each_loop() {
+        let (program, counts) = profile(NESTED);
+        let loops = loops(program.tokens(), &counts);
+
+        let summary: Vec<_> = loops.iter()
+            .map(|l| (l.start, l.steps, l.iterations, l.reached, l.innermost))
+            .collect();
+        assert_eq!(summary, [
+            (2, 107, 2, 1, false),
+            (6, 94, 4, 2, false),
+            (11, 64, 12, 4, true),
+        ]);
+    }
+
+    #[test]
+    fn folds_stacks() {
+        let (program, counts) = profile(NESTED);
+        let expected = "\
+program 7
+program;loop@1:3 13
+program;loop@1:3;loop@1:7 30
+program;loop@1:3;loop@1:7;loop@1:12 64
+";
+        assert_eq!(folded(program.tokens(), &counts), expected);
+    }
+
+    #[test]
+    fn folds_sibling_loops_with_the_same_name_separately() {
+        let (program, counts) = profile("+[-]\n+[-]");
+        assert_eq!(folded(program.tokens(), &counts), "program 3\nprogram;loop@1:2 3\nprogram;loop@2:2 3\n");
+    }
+
+    #[test]
+    fn reports_the_hottest_loops() {
+        let (program, counts) = profile(NESTED);
+        let report = report(NESTED, program.tokens(), &counts);
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "Profile: 114 steps, 3 loops");
+        assert!(lines[1].starts_with("Loops marked innermost"));
+        assert!(lines[4].contains("loop@1:3          [>++[>+++[>+<-]<-]<-]"));
+        assert!(lines[5].ends_with("loop@1:7          [>+++[>+<-]<-]"));
+        assert!(lines[6].ends_with("loop@1:12         [>+<-]  (innermost)"));
+        assert_eq!(lines.len(), 7);
+    }
+
+    #[test]
+    fn clips_long_loops_in_reports() {
+        let source = format!("+[{}-]", ">\n<".repeat(30));
+        let (program, _) = profile(&source);
+        let text = loop_text(&source, program.tokens(), 1);
+
+        assert_eq!(text.chars().count(), LOOP_TEXT_WIDTH);
+        assert!(text.starts_with("[><><"));
+        assert!(text.ends_with("..."));
+    }
+
+    #[test]
+    fn writes_folded_stacks_when_the_program_fails() {
+        let path = temp_path("folded");
+        let options = ProfileOptions { report: false, folded: Some(path.to_str().unwrap().to_string()) };
+        let program = Program::parse("+[<]").unwrap();
+
+        let result = run(&program, "+[<]", &RunConfig::default(), Tape::default(), &mut MemoryIo::<u8>::new(&[]), &options);
+        assert!(matches!(result, Err(Error::TapeUnderflow { .. })));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "program 1\nprogram;loop@1:2 2\n");
+    }
+}
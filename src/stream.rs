@@ -0,0 +1,805 @@
+use std::{iter, str, io};
+use std::collections::VecDeque;
+use std::fs::{File};
+use io::{Read, Write, BufRead, BufReader, BufWriter, Cursor};
+
+use crate::cell_size::CellSize;
+use crate::error::Error;
+
+/// The input and output used by `,` and `.`.
+///
+/// `execute` only talks to the outside world through this trait, so programs can be run against
+/// in-memory buffers as well as the process's streams.
+pub trait BfIo<T> {
+    /// Reads a value for `,`, or `None` at the end of the input.
+    fn read_cell(&mut self) -> Result<Option<T>, Error>;
+
+    /// Writes a value for `.` `count` times.
+    ///
+    /// Fails with `Error::OutputLimit` if writing would go past `--max-output`.
+    fn write_cell(&mut self, value: T, count: usize) -> Result<(), Error>;
+
+    /// Called when the program finishes.
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The number of bytes written so far.
+    fn written(&self) -> usize {
+        0
+    }
+}
+
+/// What `,` stores in the current cell once the input has run out.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Eof {
+    /// Set the cell to 0.
+    Zero,
+    /// Set the cell to -1, i.e. all bits set for the cell size.
+    MinusOne,
+    /// Leave the cell as it was.
+    Unchanged,
+}
+
+impl str::FromStr for Eof {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Eof, &'static str> {
+        match s {
+            "zero" => Ok(Eof::Zero),
+            "minus-one" => Ok(Eof::MinusOne),
+            "unchanged" => Ok(Eof::Unchanged),
+            _ => Err("Unknown EOF behaviour"),
+        }
+    }
+}
+
+/// How cell values are converted to and from the bytes read by `,` and written by `.`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IoMode {
+    /// One byte per cell. Wide cells are truncated to their low byte on output.
+    Bytes,
+    /// One UTF-8 encoded Unicode scalar value per cell.
+    Utf8,
+    /// One whitespace-separated decimal number per cell.
+    Decimal,
+}
+
+impl str::FromStr for IoMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<IoMode, &'static str> {
+        match s {
+            "bytes" => Ok(IoMode::Bytes),
+            "utf8" => Ok(IoMode::Utf8),
+            "decimal" => Ok(IoMode::Decimal),
+            _ => Err("Unknown I/O mode"),
+        }
+    }
+}
+
+/// Reads a single byte, or `None` at the end of the input.
+pub fn read_byte<R: Read>(input: &mut R) -> Result<Option<u8>, Error> {
+    let mut buf = [0];
+    match input.read_exact(&mut buf) {
+        Ok(_) => Ok(Some(buf[0])),
+        Err(x) if x.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(x) => Err(Error::Io("Could not read input", x)),
+    }
+}
+
+/// Reads a single UTF-8 encoded character, or `None` at the end of the input.
+pub fn read_char<R: Read>(input: &mut R) -> Result<Option<char>, Error> {
+    let first = match read_byte(input)? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Err(Error::InvalidData("Input is not valid UTF-8")),
+    };
+
+    let mut buf = [first, 0, 0, 0];
+    for byte in buf[1..len].iter_mut() {
+        *byte = match read_byte(input)? {
+            Some(x) => x,
+            None => return Err(Error::InvalidData("Input is not valid UTF-8")),
+        };
+    }
+
+    match str::from_utf8(&buf[..len]) {
+        Ok(x) => Ok(x.chars().next()),
+        Err(_) => Err(Error::InvalidData("Input is not valid UTF-8")),
+    }
+}
+
+/// Reads a decimal number terminated by whitespace, or `None` if only whitespace is left.
+pub fn read_number<R: Read>(input: &mut R) -> Result<Option<u64>, Error> {
+    let mut digits = String::new();
+    loop {
+        match read_byte(input)? {
+            Some(x) if x.is_ascii_whitespace() => {
+                if !digits.is_empty() {
+                    break;
+                }
+            },
+            Some(x) => digits.push(x as char),
+            None => break,
+        }
+    }
+
+    if digits.is_empty() {
+        return Ok(None);
+    }
+
+    match digits.parse() {
+        Ok(x) => Ok(Some(x)),
+        Err(_) => Err(Error::InvalidData("Input is not a valid number")),
+    }
+}
+
+/// When buffered output is written through to its destination.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Flush {
+    /// After every `.`.
+    Byte,
+    /// After every newline.
+    Line,
+    /// Only when the program finishes, or the buffer fills up.
+    Exit,
+    /// Before the program blocks waiting for input, and when it finishes.
+    Auto,
+}
+
+impl str::FromStr for Flush {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Flush, &'static str> {
+        match s {
+            "byte" => Ok(Flush::Byte),
+            "line" => Ok(Flush::Line),
+            "exit" => Ok(Flush::Exit),
+            "auto" => Ok(Flush::Auto),
+            _ => Err("Unknown flush policy"),
+        }
+    }
+}
+
+/// Buffered program output, which is flushed according to a `Flush` policy.
+pub struct Output<W: Write> {
+    inner: BufWriter<W>,
+    flush: Flush,
+    limit: Option<usize>,
+    written: usize,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(inner: W, flush: Flush) -> Output<W> {
+        Output {
+            inner: BufWriter::new(inner),
+            flush,
+            limit: None,
+            written: 0,
+        }
+    }
+
+    /// Caps the total number of bytes which can be written.
+    pub fn with_limit(mut self, limit: Option<usize>) -> Output<W> {
+        self.limit = limit;
+        self
+    }
+
+    /// Whether the limit has been used up.
+    pub fn limit_reached(&self) -> bool {
+        match self.limit {
+            Some(limit) => self.written >= limit,
+            None => false,
+        }
+    }
+
+    /// Writes `count` copies of `bytes`.
+    ///
+    /// If this would go past the limit, as much as fits is written and flushed, and an error is
+    /// returned.
+    pub fn write_bytes(&mut self, bytes: &[u8], count: usize) -> io::Result<()> {
+        for _ in 0..count {
+            if let Some(limit) = self.limit {
+                if self.written + bytes.len() > limit {
+                    self.inner.write_all(&bytes[..limit - self.written])?;
+                    self.written = limit;
+                    self.inner.flush()?;
+                    return Err(io::Error::other("output limit reached"));
+                }
+            }
+
+            self.inner.write_all(bytes)?;
+            self.written += bytes.len();
+        }
+
+        match self.flush {
+            Flush::Byte => self.inner.flush(),
+            Flush::Line if bytes.contains(&b'\n') => self.inner.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Called before the program blocks waiting for input.
+    pub fn before_input(&mut self) -> io::Result<()> {
+        match self.flush {
+            Flush::Auto => self.inner.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Called when the program finishes.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How line endings in the input are translated before `,` sees them.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Newline {
+    /// CR and CRLF both become LF (10).
+    Lf,
+    /// LF and CRLF both become CR (13).
+    Cr,
+    /// Only CRLF becomes LF; a lone CR is left alone. A CR is only joined with an LF which has
+    /// already arrived with it, so input is never waited on to decide.
+    CrlfToLf,
+}
+
+impl str::FromStr for Newline {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Newline, &'static str> {
+        match s {
+            "lf" => Ok(Newline::Lf),
+            "cr" => Ok(Newline::Cr),
+            "crlf-to-lf" => Ok(Newline::CrlfToLf),
+            _ => Err("Unknown newline translation"),
+        }
+    }
+}
+
+/// Program input, with optional newline translation and an interactive prompt.
+pub struct Input<R: Read> {
+    inner: BufReader<R>,
+    newline: Option<Newline>,
+    interactive: bool,
+    /// The last byte was a CR which has already been translated, so an LF straight after it is
+    /// the rest of a CRLF pair and is dropped.
+    pending_cr: bool,
+}
+
+impl<R: Read> Input<R> {
+    pub fn new(inner: R, newline: Option<Newline>, interactive: bool) -> Input<R> {
+        Input {
+            inner: BufReader::new(inner),
+            newline,
+            interactive,
+            pending_cr: false,
+        }
+    }
+
+    /// Reads the next untranslated byte. If `prompt` is set and the program is about to block
+    /// waiting for a new line of input, the prompt marker is printed first.
+    fn next_raw(&mut self, prompt: bool) -> io::Result<Option<u8>> {
+        if prompt && self.interactive && self.inner.buffer().is_empty() {
+            eprint!("> ");
+        }
+
+        let mut buf = [0];
+        match self.inner.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    /// Reads the next byte, translating line endings.
+    ///
+    /// A CR is translated as soon as it's read, rather than waiting to see whether an LF follows,
+    /// so that a program reading a line from a terminal doesn't block on the next one.
+    fn next(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = self.next_raw(true)?;
+        if self.pending_cr && byte == Some(b'\n') {
+            byte = self.next_raw(true)?;
+        }
+        self.pending_cr = false;
+
+        match (byte, self.newline) {
+            (Some(b'\r'), Some(Newline::CrlfToLf)) => {
+                if self.inner.buffer().first() == Some(&b'\n') {
+                    self.inner.consume(1);
+                    Ok(Some(b'\n'))
+                } else {
+                    Ok(byte)
+                }
+            },
+            (Some(b'\r'), Some(newline)) => {
+                self.pending_cr = true;
+                match newline {
+                    Newline::Cr => Ok(Some(b'\r')),
+                    _ => Ok(Some(b'\n')),
+                }
+            },
+            (Some(b'\n'), Some(Newline::Cr)) => Ok(Some(b'\r')),
+            _ => Ok(byte),
+        }
+    }
+}
+
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.next()? {
+            Some(x) => {
+                buf[0] = x;
+                Ok(1)
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+/// `BfIo` over any reader and writer, converting cells according to an `IoMode`.
+pub struct StreamIo<R: Read, W: Write> {
+    input: Input<R>,
+    output: Output<W>,
+    mode: IoMode,
+}
+
+impl<R: Read, W: Write> StreamIo<R, W> {
+    pub fn new(input: Input<R>, output: Output<W>, mode: IoMode) -> StreamIo<R, W> {
+        StreamIo {
+            input,
+            output,
+            mode,
+        }
+    }
+}
+
+impl<T, R, W> BfIo<T> for StreamIo<R, W>
+where T: CellSize,
+      R: Read,
+      W: Write,
+{
+    fn read_cell(&mut self) -> Result<Option<T>, Error> {
+        if let Err(x) = self.output.before_input() {
+            return Err(Error::Io("Could not flush output", x));
+        }
+
+        T::read_cell(&mut self.input, self.mode)
+    }
+
+    fn write_cell(&mut self, value: T, count: usize) -> Result<(), Error> {
+        match value.write_cell(&mut self.output, self.mode, count) {
+            Err(_) if self.output.limit_reached() => Err(Error::OutputLimit),
+            x => x,
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.output.finish() {
+            Ok(_) => Ok(()),
+            Err(x) => Err(Error::Io("Could not flush output", x)),
+        }
+    }
+
+    fn written(&self) -> usize {
+        self.output.written
+    }
+}
+
+/// `BfIo` over cells in memory: `,` takes values from `input`, and `.` appends to `output`.
+///
+/// Handy for embedding, and for running programs whose output is only inspected afterwards.
+pub struct MemoryIo<T> {
+    pub input: VecDeque<T>,
+    pub output: Vec<T>,
+}
+
+impl<T: Copy> MemoryIo<T> {
+    pub fn new(input: &[T]) -> MemoryIo<T> {
+        MemoryIo {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy> BfIo<T> for MemoryIo<T> {
+    fn read_cell(&mut self) -> Result<Option<T>, Error> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write_cell(&mut self, value: T, count: usize) -> Result<(), Error> {
+        self.output.extend(iter::repeat_n(value, count));
+        Ok(())
+    }
+}
+
+/// `StreamIo` over whichever streams `IoOptions` chose.
+pub type ProcessIo = StreamIo<Box<dyn Read>, Box<dyn Write>>;
+
+/// Options choosing where `,` reads from and `.` writes to, and how.
+pub struct IoOptions {
+    pub input: Option<String>,
+    pub input_string: Option<String>,
+    pub input_hex: Option<String>,
+    pub output: Option<String>,
+    pub mode: IoMode,
+    pub flush: Flush,
+    pub max_output: Option<usize>,
+    pub newline: Option<Newline>,
+    pub interactive: bool,
+}
+
+impl IoOptions {
+    /// Whether `,` reads from stdin, because no other input was given.
+    pub fn reads_stdin(&self) -> bool {
+        self.input.is_none() && self.input_string.is_none() && self.input_hex.is_none()
+    }
+
+    /// Opens the program's input and output.
+    pub fn open(&self) -> Result<ProcessIo, Error> {
+        let input = self.open_input()?;
+        let output = Output::new(self.open_output()?, self.flush)
+            .with_limit(self.max_output);
+
+        Ok(StreamIo::new(input, output, self.mode))
+    }
+
+    /// Opens the program's input: a file, a literal string, hex-encoded bytes, or stdin.
+    fn open_input(&self) -> Result<Input<Box<dyn Read>>, Error> {
+        let given = [&self.input, &self.input_string, &self.input_hex].iter()
+            .filter(|x| x.is_some())
+            .count();
+        if given > 1 {
+            return Err(Error::Config("Only one of --input, --input-string and --input-hex can be used"));
+        }
+
+        let inner: Box<dyn Read> = if let Some(filename) = &self.input {
+            match File::open(filename) {
+                Ok(file) => Box::new(file),
+                Err(x) => return Err(Error::Io("Could not open input file", x)),
+            }
+        } else if let Some(string) = &self.input_string {
+            Box::new(Cursor::new(string.clone().into_bytes()))
+        } else if let Some(hex) = &self.input_hex {
+            Box::new(Cursor::new(parse_hex(hex)?))
+        } else {
+            Box::new(io::stdin())
+        };
+
+        Ok(Input::new(inner, self.newline, self.interactive))
+    }
+
+    /// Opens the program's output: a file, or stdout.
+    fn open_output(&self) -> Result<Box<dyn Write>, Error> {
+        match &self.output {
+            Some(filename) => match File::create(filename) {
+                Ok(file) => Ok(Box::new(file)),
+                Err(x) => Err(Error::Io("Could not create output file", x)),
+            },
+            None => Ok(Box::new(io::stdout())),
+        }
+    }
+}
+
+/// Decodes a string of hex digit pairs, which may be separated by whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(Error::Config("Input hex must have an even number of digits"));
+    }
+
+    let mut bytes = Vec::new();
+    for pair in digits.chunks(2) {
+        let pair: String = pair.iter().collect();
+        match u8::from_str_radix(&pair, 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(Error::Config("Input hex contains an invalid digit")),
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+    use crate::cell_size::CellSize;
+    use crate::temp::{temp_file, temp_path};
+    use crate::testing::run_stream;
+
+    /// A writer which shares what has been written through to it, so tests can see when the
+    /// buffer in front of it is flushed.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn contents(&self) -> Vec<u8> {
+            self.0.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn parses_eof_behaviours() {
+        assert_eq!("zero".parse(), Ok(Eof::Zero));
+        assert_eq!("minus-one".parse(), Ok(Eof::MinusOne));
+        assert_eq!("unchanged".parse(), Ok(Eof::Unchanged));
+        assert_eq!("-1".parse::<Eof>(), Err("Unknown EOF behaviour"));
+    }
+
+    #[test]
+    fn flush_byte_writes_every_byte_through() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Byte);
+        output.write_bytes(b"a", 1).unwrap();
+        assert_eq!(shared.contents(), b"a");
+    }
+
+    #[test]
+    fn flush_line_waits_for_a_newline() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Line);
+        output.write_bytes(b"a", 1).unwrap();
+        assert_eq!(shared.contents(), b"");
+        output.write_bytes(b"\n", 1).unwrap();
+        assert_eq!(shared.contents(), b"a\n");
+    }
+
+    #[test]
+    fn flush_auto_waits_for_input_or_the_end() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Auto);
+        output.write_bytes(b"\n", 1).unwrap();
+        assert_eq!(shared.contents(), b"");
+        output.before_input().unwrap();
+        assert_eq!(shared.contents(), b"\n");
+        output.write_bytes(b"b", 1).unwrap();
+        output.finish().unwrap();
+        assert_eq!(shared.contents(), b"\nb");
+    }
+
+    #[test]
+    fn flush_exit_waits_for_the_end() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Exit);
+        output.write_bytes(b"\n", 1).unwrap();
+        output.before_input().unwrap();
+        assert_eq!(shared.contents(), b"");
+        output.finish().unwrap();
+        assert_eq!(shared.contents(), b"\n");
+    }
+
+    #[test]
+    fn writes_repeated_output() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Exit);
+        output.write_bytes(b"ab", 3).unwrap();
+        output.finish().unwrap();
+        assert_eq!(shared.contents(), b"ababab");
+    }
+
+    #[test]
+    fn parses_flush_policies() {
+        assert_eq!("byte".parse(), Ok(Flush::Byte));
+        assert_eq!("auto".parse(), Ok(Flush::Auto));
+        assert_eq!("never".parse::<Flush>(), Err("Unknown flush policy"));
+    }
+
+    fn default_options() -> IoOptions {
+        IoOptions {
+            input: None,
+            input_string: None,
+            input_hex: None,
+            output: None,
+            mode: IoMode::Bytes,
+            flush: Flush::Exit,
+            max_output: None,
+            newline: None,
+            interactive: false,
+        }
+    }
+
+    /// Copies the input given by the options to their output.
+    fn echo(options: &IoOptions) -> Result<(), Error> {
+        let mut input = options.open_input()?;
+        let mut output = options.open_output()?;
+        io::copy(&mut input, &mut output).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn redirects_input_and_output_to_files() {
+        let input = temp_file("input", b"file input");
+        let output = temp_path("output");
+        let options = IoOptions {
+            input: Some(input.to_str().unwrap().to_string()),
+            output: Some(output.to_str().unwrap().to_string()),
+            ..default_options()
+        };
+
+        echo(&options).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"file input");
+    }
+
+    #[test]
+    fn reads_input_from_a_string_or_hex() {
+        let output = temp_path("output");
+        let options = IoOptions {
+            input_string: Some(String::from("string")),
+            output: Some(output.to_str().unwrap().to_string()),
+            ..default_options()
+        };
+        echo(&options).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"string");
+
+        let options = IoOptions {
+            input_hex: Some(String::from("68 69 0a")),
+            output: Some(output.to_str().unwrap().to_string()),
+            ..default_options()
+        };
+        echo(&options).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"hi\n");
+    }
+
+    #[test]
+    fn reads_stdin_without_other_input() {
+        assert!(default_options().reads_stdin());
+        assert!(!IoOptions { input_hex: Some(String::from("61")), ..default_options() }.reads_stdin());
+    }
+
+    #[test]
+    fn rejects_more_than_one_input() {
+        let options = IoOptions {
+            input_string: Some(String::from("a")),
+            input_hex: Some(String::from("61")),
+            ..default_options()
+        };
+        assert!(options.open_input().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        assert!(parse_hex("616").is_err());
+        assert!(parse_hex("6g").is_err());
+        assert_eq!(parse_hex("61 62\n63").unwrap(), b"abc");
+    }
+
+    #[test]
+    fn fails_to_open_a_missing_input_file() {
+        let options = IoOptions {
+            input: Some(temp_path("missing").to_str().unwrap().to_string()),
+            ..default_options()
+        };
+        assert!(options.open_input().is_err());
+    }
+
+    #[test]
+    fn utf8_reads_and_writes_characters() {
+        let input = "h\u{e9}llo \u{20ac}\u{1f600}".as_bytes();
+        let out = run_stream::<u32>(",[.,]", input, IoMode::Utf8, None).unwrap();
+        assert_eq!(out, input);
+
+        let out = run_stream::<u32>(",.", "\u{20ac}".as_bytes(), IoMode::Bytes, None).unwrap();
+        assert_eq!(out, [0xe2]);
+    }
+
+    #[test]
+    fn utf8_rejects_characters_too_large_for_the_cells() {
+        let out = run_stream::<u8>(",.", "\u{e9}".as_bytes(), IoMode::Utf8, None).unwrap();
+        assert_eq!(out, "\u{e9}".as_bytes());
+
+        let res = run_stream::<u8>(",.", "\u{20ac}".as_bytes(), IoMode::Utf8, None);
+        assert!(matches!(res, Err(Error::InvalidData("Input value is too large for the cell size"))));
+    }
+
+    #[test]
+    fn utf8_rejects_invalid_input_and_output() {
+        assert!(read_char(&mut &b"\xff"[..]).is_err());
+        assert!(read_char(&mut &b"\xe2\x82"[..]).is_err());
+
+        let mut output = Output::new(Vec::new(), Flush::Exit);
+        assert!(0xd800u32.write_cell(&mut output, IoMode::Utf8, 1).is_err());
+    }
+
+    #[test]
+    fn decimal_reads_and_writes_numbers() {
+        let out = run_stream::<u32>(",.,.,.", b"  300\n70000\t", IoMode::Decimal, None).unwrap();
+        assert_eq!(out, b"300\n70000\n0\n");
+    }
+
+    #[test]
+    fn decimal_rejects_bad_numbers() {
+        assert!(run_stream::<u8>(",.", b"300", IoMode::Decimal, None).is_err());
+        assert!(run_stream::<u32>(",.", b"12a", IoMode::Decimal, None).is_err());
+    }
+
+    fn translate(input: &[u8], newline: Option<Newline>) -> Vec<u8> {
+        let mut out = Vec::new();
+        Input::new(input, newline, false).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn newline_lf_translates_cr_and_crlf() {
+        assert_eq!(translate(b"a\r\nb\rc\nd\r", Some(Newline::Lf)), b"a\nb\nc\nd\n");
+    }
+
+    #[test]
+    fn newline_cr_translates_lf_and_crlf() {
+        assert_eq!(translate(b"a\r\nb\rc\nd", Some(Newline::Cr)), b"a\rb\rc\rd");
+    }
+
+    #[test]
+    fn newline_crlf_to_lf_leaves_lone_cr() {
+        assert_eq!(translate(b"a\r\nb\rc\n\r", Some(Newline::CrlfToLf)), b"a\nb\rc\n\r");
+    }
+
+    /// Gives `bytes` in one read, and fails if asked for any more, as a terminal would block.
+    struct Once<'a>(Option<&'a [u8]>);
+
+    impl Read for Once<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let bytes = self.0.take().expect("read past the end of the line");
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Ok(bytes.len())
+        }
+    }
+
+    #[test]
+    fn newline_translation_does_not_read_past_a_cr() {
+        for (newline, cr) in [(Newline::Lf, b'\n'), (Newline::Cr, b'\r'), (Newline::CrlfToLf, b'\r')] {
+            let mut input = Input::new(Once(Some(b"a\r")), Some(newline), false);
+            assert_eq!(input.next().unwrap(), Some(b'a'));
+            assert_eq!(input.next().unwrap(), Some(cr));
+        }
+
+        // The LF of a CRLF arriving in a later read is still dropped
+        let mut input = Input::new(Cursor::new(b"\r").chain(&b"\nb"[..]), Some(Newline::Lf), false);
+        let mut out = Vec::new();
+        input.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"\nb");
+    }
+
+    #[test]
+    fn newline_translation_is_off_by_default() {
+        assert_eq!(translate(b"a\r\nb\r", None), b"a\r\nb\r");
+    }
+
+    #[test]
+    fn output_limit_writes_as_much_as_fits() {
+        let shared = Shared::default();
+        let mut output = Output::new(shared.clone(), Flush::Exit).with_limit(Some(5));
+        output.write_bytes(b"ab", 2).unwrap();
+        assert!(!output.limit_reached());
+
+        assert!(output.write_bytes(b"cd", 1).is_err());
+        assert!(output.limit_reached());
+        assert_eq!(shared.contents(), b"ababc");
+    }
+}
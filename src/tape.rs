@@ -0,0 +1,272 @@
+use std::{str, fs, mem};
+
+use crate::cell_size::CellSize;
+use crate::error::Error;
+
+/// The data tape and the position of the data pointer on it.
+pub struct Tape<T> {
+    pub cells: Vec<T>,
+    pub data_pointer: usize,
+}
+
+impl<T> Default for Tape<T> {
+    /// An empty tape, which is filled with zeroes as the program uses it.
+    fn default() -> Tape<T> {
+        Tape {
+            cells: Vec::new(),
+            data_pointer: 0,
+        }
+    }
+}
+
+impl<T> Tape<T>
+where T: CellSize
+{
+    /// Copies the tape into cells of another size, truncating values which don't fit.
+    pub fn convert<U>(&self) -> Tape<U>
+    where U: CellSize
+    {
+        Tape {
+            cells: self.cells.iter().map(|c| U::from_u64(c.to_u64())).collect(),
+            data_pointer: self.data_pointer,
+        }
+    }
+}
+
+/// How a file given to `--tape-init` is read.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InitFormat {
+    /// Each byte of the file is loaded into one cell.
+    Raw,
+    /// The file is a whitespace-separated list of numbers, one per cell.
+    Numbers,
+}
+
+impl str::FromStr for InitFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<InitFormat, &'static str> {
+        match s {
+            "raw" => Ok(InitFormat::Raw),
+            "numbers" => Ok(InitFormat::Numbers),
+            _ => Err("Unknown tape init format"),
+        }
+    }
+}
+
+/// How the tape is written out by `--dump-tape`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DumpFormat {
+    /// The data pointer, then rows of 16 cells in zero-padded hex, each led by the index of its
+    /// first cell.
+    Hex,
+    /// The data pointer, then rows of 16 cells in decimal.
+    Decimal,
+    /// One JSON object holding the data pointer, the cell size in bits and the cells.
+    Json,
+}
+
+impl str::FromStr for DumpFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<DumpFormat, &'static str> {
+        match s {
+            "hex" => Ok(DumpFormat::Hex),
+            "decimal" => Ok(DumpFormat::Decimal),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err("Unknown tape dump format"),
+        }
+    }
+}
+
+/// Options for loading the tape before execution and dumping it afterwards.
+pub struct TapeOptions {
+    pub init: Option<String>,
+    pub init_format: InitFormat,
+    pub dump: Option<String>,
+    pub dump_format: DumpFormat,
+}
+
+/// Reads the initial contents of the tape from a file.
+///
+/// Numbers which don't fit in a cell of type `T` are rejected rather than truncated.
+pub fn load<T>(filename: &str, format: InitFormat, tape_size: usize) -> Result<Vec<T>, Error>
+where T: CellSize
+{
+    let cells: Vec<T> = match format {
+        InitFormat::Raw => {
+            let bytes = match fs::read(filename) {
+                Ok(x) => x,
+                Err(x) => return Err(Error::Io("Could not open tape init file", x)),
+            };
+
+            bytes.into_iter().map(T::from_stdin).collect()
+        },
+        InitFormat::Numbers => {
+            let text = match fs::read_to_string(filename) {
+                Ok(x) => x,
+                Err(x) => return Err(Error::Io("Could not open tape init file", x)),
+            };
+
+            let mut cells = Vec::new();
+            for word in text.split_whitespace() {
+                let value: u64 = match word.parse() {
+                    Ok(x) => x,
+                    Err(_) => return Err(Error::InvalidData("Tape init file contains an invalid number")),
+                };
+
+                let cell = T::from_u64(value);
+                if cell.to_u64() != value {
+                    return Err(Error::InvalidData("Tape init file contains a number too large for the cell size"));
+                }
+
+                cells.push(cell);
+            }
+
+            cells
+        },
+    };
+
+    if cells.len() > tape_size {
+        return Err(Error::InvalidData("Tape init file is larger than the tape"));
+    }
+
+    Ok(cells)
+}
+
+/// Formats the tape for `--dump-tape`.
+///
+/// Trailing zero cells beyond the data pointer are left out.
+pub fn dump<T>(tape: &Tape<T>, format: DumpFormat) -> String
+where T: CellSize
+{
+    let used = match tape.cells.iter().rposition(|c| c.is_nonzero()) {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let len = used.max(tape.data_pointer + 1).min(tape.cells.len());
+    let cells = &tape.cells[..len];
+
+    match format {
+        DumpFormat::Hex => {
+            let width = mem::size_of::<T>() * 2;
+            let mut out = format!("data pointer: {:#x}\n", tape.data_pointer);
+            for (i, row) in cells.chunks(16).enumerate() {
+                let row: Vec<String> = row.iter()
+                    .map(|c| format!("{:0width$x}", c.to_u64(), width = width))
+                    .collect();
+                out.push_str(&format!("{:08x}: {}\n", i * 16, row.join(" ")));
+            }
+            out
+        },
+        DumpFormat::Decimal => {
+            let mut out = format!("data pointer: {}\n", tape.data_pointer);
+            for row in cells.chunks(16) {
+                let row: Vec<String> = row.iter()
+                    .map(|c| c.to_u64().to_string())
+                    .collect();
+                out.push_str(&row.join(" "));
+                out.push('\n');
+            }
+            out
+        },
+        DumpFormat::Json => {
+            let cells: Vec<String> = cells.iter()
+                .map(|c| c.to_u64().to_string())
+                .collect();
+            format!(
+                "{{\"data_pointer\": {}, \"cell_bits\": {}, \"cells\": [{}]}}\n",
+                tape.data_pointer,
+                mem::size_of::<T>() * 8,
+                cells.join(", ")
+            )
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::RunConfig;
+    use crate::optimize::OptConfig;
+    use crate::stream::MemoryIo;
+    use crate::temp::temp_file;
+    use crate::{Halt, Program};
+
+    fn run_from(source: &str, tape: Tape<u8>) -> Tape<u8> {
+        let mut program = Program::parse(source).unwrap();
+        program.optimize(&OptConfig::default());
+        match program.run_with_tape(&RunConfig::default(), tape, &mut MemoryIo::new(&[])).unwrap() {
+            Halt::End(tape) => tape,
+            Halt::OutputLimit(..) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn loads_raw_bytes() {
+        let path = temp_file("raw", &[1, 2, 255]);
+        let cells: Vec<u16> = load(path.to_str().unwrap(), InitFormat::Raw, 30000).unwrap();
+        assert_eq!(cells, vec![1, 2, 255]);
+    }
+
+    #[test]
+    fn loads_numbers() {
+        let path = temp_file("numbers", b"1 300\n 65535\t0");
+        let cells: Vec<u16> = load(path.to_str().unwrap(), InitFormat::Numbers, 30000).unwrap();
+        assert_eq!(cells, vec![1, 300, 65535, 0]);
+    }
+
+    #[test]
+    fn rejects_numbers_too_large_for_the_cells() {
+        let path = temp_file("large", b"1 256");
+        let res = load::<u8>(path.to_str().unwrap(), InitFormat::Numbers, 30000);
+        assert!(matches!(res, Err(Error::InvalidData("Tape init file contains a number too large for the cell size"))));
+
+        let path = temp_file("invalid", b"1 two");
+        let res = load::<u8>(path.to_str().unwrap(), InitFormat::Numbers, 30000);
+        assert!(matches!(res, Err(Error::InvalidData("Tape init file contains an invalid number"))));
+    }
+
+    #[test]
+    fn rejects_files_larger_than_the_tape() {
+        let path = temp_file("long", &[0; 10]);
+        let res = load::<u8>(path.to_str().unwrap(), InitFormat::Raw, 5);
+        assert!(matches!(res, Err(Error::InvalidData("Tape init file is larger than the tape"))));
+    }
+
+    #[test]
+    fn runs_from_the_initial_tape() {
+        let tape = run_from("[->+<]>", Tape { cells: vec![3, 4], data_pointer: 0 });
+        assert_eq!(&tape.cells[..3], &[0, 7, 0]);
+        assert_eq!(tape.data_pointer, 1);
+    }
+
+    #[test]
+    fn grows_the_tape_when_moving_onto_its_end() {
+        let source = format!("{}+", ">".repeat(1000));
+        let tape = run_from(&source, Tape { cells: Vec::new(), data_pointer: 0 });
+        assert_eq!(tape.cells[1000], 1);
+        assert_eq!(tape.data_pointer, 1000);
+    }
+
+    #[test]
+    fn dumps_hex() {
+        let tape: Tape<u16> = Tape { cells: vec![1, 0x1ff, 0, 0], data_pointer: 0 };
+        assert_eq!(dump(&tape, DumpFormat::Hex), "data pointer: 0x0\n00000000: 0001 01ff\n");
+    }
+
+    #[test]
+    fn dumps_decimal_up_to_the_data_pointer() {
+        let tape: Tape<u8> = Tape { cells: vec![5, 0, 0, 0, 0], data_pointer: 2 };
+        assert_eq!(dump(&tape, DumpFormat::Decimal), "data pointer: 2\n5 0 0\n");
+    }
+
+    #[test]
+    fn dumps_json() {
+        let tape: Tape<u32> = Tape { cells: vec![7, 70000], data_pointer: 1 };
+        assert_eq!(
+            dump(&tape, DumpFormat::Json),
+            "{\"data_pointer\": 1, \"cell_bits\": 32, \"cells\": [7, 70000]}\n"
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Temporary files for the unit tests, removed again when they go out of scope.
+//!
+//! Only uses `std`, so both the library's tests and the binary's tests declare it as a module.
+//! Neither uses every helper.
+
+#![allow(dead_code)]
+
+use std::{env, fs, process};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A path in the temporary directory which no other test uses. Whatever is at the path when it's
+/// dropped, a file or a directory, is deleted.
+pub struct TempPath(PathBuf);
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // Nothing may have been created at the path, so failures are ignored
+        if self.0.is_dir() {
+            let _ = fs::remove_dir_all(&self.0);
+        } else {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Reserves a fresh path, without creating anything there.
+pub fn temp_path(name: &str) -> TempPath {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    TempPath(env::temp_dir().join(format!("rustfuck-test-{}-{}-{}", process::id(), id, name)))
+}
+
+/// Writes `contents` to a fresh temporary file.
+pub fn temp_file(name: &str, contents: &[u8]) -> TempPath {
+    let path = temp_path(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Creates a fresh, empty temporary directory.
+pub fn temp_dir(name: &str) -> TempPath {
+    let path = temp_path(name);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
@@ -0,0 +1,41 @@
+//! Helpers shared by the unit tests.
+
+use crate::cell_size::CellSize;
+use crate::error::Error;
+use crate::execute::{Halt, RunConfig};
+use crate::optimize::OptConfig;
+use crate::stream::{Flush, Input, IoMode, MemoryIo, Newline, Output, StreamIo};
+use crate::tape::Tape;
+use crate::Program;
+
+/// Parses and optimizes `source`, then runs it with in-memory I/O. Returns the output and the
+/// final tape.
+pub fn run<T>(source: &str, config: &RunConfig, input: &[T]) -> Result<(Vec<T>, Tape<T>), Error>
+where T: CellSize + Clone + Copy
+{
+    let mut program = Program::parse(source)?;
+    program.optimize(&OptConfig::default());
+
+    let mut io = MemoryIo::new(input);
+    match program.run(config, &mut io)? {
+        Halt::End(tape) => Ok((io.output, tape)),
+        Halt::OutputLimit(..) => unreachable!("MemoryIo has no output limit"),
+    }
+}
+
+/// Runs `source` with cells of type `T` through a `StreamIo` over in-memory buffers, returning the
+/// bytes written.
+pub fn run_stream<T>(source: &str, input: &[u8], mode: IoMode, newline: Option<Newline>) -> Result<Vec<u8>, Error>
+where T: CellSize + Clone + Copy
+{
+    let program = Program::parse(source)?;
+
+    let mut out = Vec::new();
+    {
+        let input = Input::new(input, newline, false);
+        let mut io = StreamIo::new(input, Output::new(&mut out, Flush::Exit), mode);
+        program.run::<T, _>(&RunConfig::default(), &mut io)?;
+    }
+
+    Ok(out)
+}
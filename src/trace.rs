@@ -0,0 +1,299 @@
+//! `--trace`, which logs every token the program runs to a file.
+//!
+//! The text format has a header line starting with `#`, then one line per token with tab-separated
+//! columns: the instruction pointer, the data pointer, the value of the current cell before the
+//! token ran, the token as shown by `--dump`, and its source position as `LINE:COL`, or `-` if it
+//! isn't known.
+//!
+//! The binary format starts with the magic bytes `BFT\0`, a little-endian `u16` format version
+//! (currently 1) and the cell size in bits as a single byte. Each token then takes three unsigned
+//! LEB128 numbers: the instruction pointer, the data pointer and the cell value. Tokens and source
+//! positions can be looked up by instruction pointer in `--dump --dump-format json`.
+
+use rustfuck::{Program, RunConfig, CellSize, Error, Tape, Token, TokenType};
+use rustfuck::stream::BfIo;
+
+use crate::debug;
+
+use std::{fs, mem, str};
+use std::io::{self, BufWriter, Write};
+
+/// The version of the binary trace format.
+const FORMAT_VERSION: u16 = 1;
+
+/// How the trace file is written.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    /// One line of text per token.
+    Text,
+    /// A compact encoding for long runs.
+    Binary,
+}
+
+impl str::FromStr for TraceFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<TraceFormat, &'static str> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err("Unknown trace format"),
+        }
+    }
+}
+
+/// Options for `--trace`.
+pub struct TraceOptions {
+    /// The file to write the trace to. Nothing is traced without one.
+    pub file: Option<String>,
+    pub format: TraceFormat,
+    /// Only trace tokens which start in this source range, `START-END`, where each end is `LINE`
+    /// or `LINE:COL`, or a single `LINE`.
+    pub range: Option<String>,
+    /// Only trace tokens in the loop starting at this location, as accepted by `debug::locate`.
+    pub in_loop: Option<String>,
+}
+
+/// Works out the byte offsets a `--trace-range` covers, with the end excluded. A range without a
+/// `-` covers just that line or position.
+fn parse_range(source: &str, range: &str) -> Option<(usize, usize)> {
+    let point = |s: &str| -> Option<(usize, Option<usize>)> {
+        match s.split_once(':') {
+            Some((line, col)) => Some((line.trim().parse().ok()?, Some(col.trim().parse().ok()?))),
+            None => Some((s.trim().parse().ok()?, None)),
+        }
+    };
+
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start_line, start_col) = point(start)?;
+    let (end_line, end_col) = point(end)?;
+
+    let start = debug::offset_of(source, start_line, start_col.unwrap_or(1)).unwrap_or(source.len());
+    // A line on its own includes the whole line
+    let end = match end_col {
+        Some(col) => debug::offset_of(source, end_line, col).map_or(source.len(), |x| x + 1),
+        None => debug::offset_of(source, end_line + 1, 1).unwrap_or(source.len()),
+    };
+
+    Some((start, end))
+}
+
+/// Decides which tokens are traced, according to the filters in `options`.
+fn filter(source: &str, tokens: &[Token], options: &TraceOptions) -> Result<Vec<bool>, Error> {
+    let mut traced = vec![true; tokens.len()];
+
+    if let Some(range) = &options.range {
+        let (start, end) = match parse_range(source, range) {
+            Some(x) => x,
+            None => return Err(Error::Config("Invalid --trace-range. Use START-END, where each is LINE or LINE:COL, or a single LINE.")),
+        };
+        for (traced, token) in traced.iter_mut().zip(tokens) {
+            *traced &= token.span.line > 0 && start <= token.span.start && token.span.start < end;
+        }
+    }
+
+    if let Some(location) = &options.in_loop {
+        let start = match debug::locate(source, tokens, location) {
+            Ok(x) if tokens[x].tk == TokenType::LoopStart => x,
+            _ => return Err(Error::Config("--trace-loop must point at a [ which wasn't optimized away, e.g. LINE:COL or #INDEX")),
+        };
+        let end = tokens[start].value as usize;
+        for (i, traced) in traced.iter_mut().enumerate() {
+            *traced &= start <= i && i <= end;
+        }
+    }
+
+    Ok(traced)
+}
+
+/// Writes `value` as an unsigned LEB128 number.
+fn write_leb128<W: Write>(out: &mut W, mut value: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+
+    out.write_all(&bytes[..len])
+}
+
+/// Writes the trace of one token, before it runs.
+fn write_record<W: Write>(out: &mut W, format: TraceFormat, ip: usize, dp: usize, cell: u64, token: &Token) -> io::Result<()> {
+    match format {
+        TraceFormat::Text => {
+            if token.span.line > 0 {
+                writeln!(out, "{}\t{}\t{}\t{}\t{}:{}", ip, dp, cell, token, token.span.line, token.span.col)
+            } else {
+                writeln!(out, "{}\t{}\t{}\t{}\t-", ip, dp, cell, token)
+            }
+        },
+        TraceFormat::Binary => {
+            write_leb128(out, ip as u64)?;
+            write_leb128(out, dp as u64)?;
+            write_leb128(out, cell)
+        },
+    }
+}
+
+/// Runs the program, writing a trace of each token to the file chosen in `options`. The trace
+/// never goes to the program's own output.
+///
+/// Returns the final tape, or the error the program failed with.
+pub fn run<T, I>(program: &Program, source: &str, config: &RunConfig, tape: Tape<T>, io: &mut I, options: &TraceOptions) -> Result<Tape<T>, Error>
+where T: CellSize + Clone + Copy,
+      I: BfIo<T>,
+{
+    let tokens = program.tokens();
+    let traced = filter(source, tokens, options)?;
+
+    let file = match &options.file {
+        Some(filename) => match fs::File::create(filename) {
+            Ok(x) => x,
+            Err(x) => return Err(Error::Io("Could not create trace file", x)),
+        },
+        None => return Err(Error::Config("No trace file given")),
+    };
+    let mut out = BufWriter::new(file);
+
+    let header = match options.format {
+        TraceFormat::Text => writeln!(out, "# ip\tdp\tcell\ttoken\tposition"),
+        TraceFormat::Binary => {
+            let mut header = b"BFT\0".to_vec();
+            header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+            header.push((mem::size_of::<T>() * 8) as u8);
+            out.write_all(&header)
+        },
+    };
+    if let Err(x) = header {
+        return Err(Error::Io("Could not write trace file", x));
+    }
+
+    let mut interpreter = program.interpreter(config, tape)?;
+    rustfuck::drive(&mut interpreter, io, |interpreter| {
+        let ip = interpreter.instruction_pointer();
+        if traced[ip] {
+            let dp = interpreter.data_pointer();
+            let cell = interpreter.cells().get(dp).map_or(0, |c| c.to_u64());
+            if let Err(x) = write_record(&mut out, options.format, ip, dp, cell, &tokens[ip]) {
+                return Err(Error::Io("Could not write trace file", x));
+            }
+        }
+
+        Ok(true)
+    })?;
+
+    if let Err(x) = out.flush() {
+        return Err(Error::Io("Could not write trace file", x));
+    }
+
+    Ok(interpreter.into_tape())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::temp_path;
+    use rustfuck::stream::MemoryIo;
+
+    const SOURCE: &str = "+[\n->+<\n]\t.";
+
+    fn options(range: Option<&str>, in_loop: Option<&str>) -> TraceOptions {
+        TraceOptions {
+            file: None,
+            format: TraceFormat::Text,
+            range: range.map(String::from),
+            in_loop: in_loop.map(String::from),
+        }
+    }
+
+    /// The indices of the tokens traced with the given filters.
+    fn traced(range: Option<&str>, in_loop: Option<&str>) -> Result<Vec<usize>, Error> {
+        let program = Program::parse(SOURCE).unwrap();
+        let traced = filter(SOURCE, program.tokens(), &options(range, in_loop))?;
+        Ok((0..traced.len()).filter(|&i| traced[i]).collect())
+    }
+
+    /// Runs `source` with tracing to a temporary file, and returns what was written to it.
+    fn trace(source: &str, format: TraceFormat) -> Vec<u8> {
+        let path = temp_path("trace");
+        let options = TraceOptions {
+            file: Some(path.to_str().unwrap().to_string()),
+            format,
+            ..options(None, None)
+        };
+
+        let program = Program::parse(source).unwrap();
+        run::<u8, _>(&program, source, &RunConfig::default(), Tape::default(), &mut MemoryIo::new(&[]), &options).unwrap();
+
+        fs::read(&path).unwrap()
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range(SOURCE, "2"), Some((3, 8)));
+        assert_eq!(parse_range(SOURCE, "1-2"), Some((0, 8)));
+        assert_eq!(parse_range(SOURCE, "2:2-3:1"), Some((4, 9)));
+        // Ranges past the end stop at the end of the source
+        assert_eq!(parse_range(SOURCE, "3-9"), Some((8, 11)));
+        assert_eq!(parse_range(SOURCE, "a-2"), None);
+        assert_eq!(parse_range(SOURCE, "1:x"), None);
+    }
+
+    #[test]
+    fn filters_by_range_and_loop() {
+        assert_eq!(traced(None, None).unwrap(), (0..9).collect::<Vec<_>>());
+        assert_eq!(traced(Some("2"), None).unwrap(), [2, 3, 4, 5]);
+        assert_eq!(traced(None, Some("1:2")).unwrap(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(traced(Some("3"), Some("#1")).unwrap(), [6]);
+
+        assert!(matches!(traced(Some("x"), None), Err(Error::Config(_))));
+        assert!(matches!(traced(None, Some("#0")), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn writes_leb128() {
+        let encode = |value| {
+            let mut out = Vec::new();
+            write_leb128(&mut out, value).unwrap();
+            out
+        };
+
+        assert_eq!(encode(0), [0]);
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+        assert_eq!(encode(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(encode(u64::MAX).len(), 10);
+    }
+
+    #[test]
+    fn writes_text_traces() {
+        let expected = "\
+# ip\tdp\tcell\ttoken\tposition
+0\t0\t0\t+\t1:1
+1\t0\t1\t.\t1:2
+2\t0\t1\t:\t1:3
+";
+        assert_eq!(String::from_utf8(trace("+.", TraceFormat::Text)).unwrap(), expected);
+    }
+
+    #[test]
+    fn writes_binary_traces() {
+        let expected = [b'B', b'F', b'T', 0, 1, 0, 8, 0, 0, 0, 1, 0, 1];
+        assert_eq!(trace("+", TraceFormat::Binary), expected);
+    }
+
+    #[test]
+    fn parses_trace_formats() {
+        assert_eq!("text".parse(), Ok(TraceFormat::Text));
+        assert_eq!("binary".parse(), Ok(TraceFormat::Binary));
+        assert_eq!("csv".parse::<TraceFormat>(), Err("Unknown trace format"));
+    }
+}